@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::error::Error;
+
+// A column that the program knows how to read.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Column
+{
+    OrderDate,
+    OrderNumber,
+    Shipping,
+    Tax,
+    Total,
+    Title,
+    Category,
+    Quantity,
+    ItemSubtotal,
+    ItemTax,
+    ItemTotal,
+    RefundDate,
+    RefundAmount,
+    RefundTax,
+    RefundShipping,
+    Payment,
+    Promotion,
+    GiftCard,
+    BankDate,
+    BankAmount,
+    BankDescription,
+    ShipmentDate,
+}
+
+impl Column
+{
+    pub const ALL: &'static [Column] = &[
+        Column::OrderDate, Column::OrderNumber, Column::Shipping,
+        Column::Tax, Column::Total, Column::Title, Column::Category,
+        Column::Quantity, Column::ItemSubtotal, Column::ItemTax,
+        Column::ItemTotal, Column::RefundDate, Column::RefundAmount,
+        Column::RefundTax, Column::RefundShipping, Column::Payment,
+        Column::Promotion, Column::GiftCard, Column::BankDate,
+        Column::BankAmount, Column::BankDescription, Column::ShipmentDate,
+    ];
+
+    // The name of the column, as used in the config file.
+    pub fn name(&self) -> &'static str
+    {
+        match self
+        {
+            Column::OrderDate => "order_date",
+            Column::OrderNumber => "order_number",
+            Column::Shipping => "shipping",
+            Column::Tax => "tax",
+            Column::Total => "total",
+            Column::Title => "title",
+            Column::Category => "category",
+            Column::Quantity => "quantity",
+            Column::ItemSubtotal => "item_subtotal",
+            Column::ItemTax => "item_tax",
+            Column::ItemTotal => "item_total",
+            Column::RefundDate => "refund_date",
+            Column::RefundAmount => "refund_amount",
+            Column::RefundTax => "refund_tax",
+            Column::RefundShipping => "refund_shipping",
+            Column::Payment => "payment",
+            Column::Promotion => "promotion",
+            Column::GiftCard => "gift_card",
+            Column::BankDate => "bank_date",
+            Column::BankAmount => "bank_amount",
+            Column::BankDescription => "bank_description",
+            Column::ShipmentDate => "shipment_date",
+        }
+    }
+}
+
+// The header names that each column may appear under in the CSV.
+// Matching is case-insensitive. A list in the config file replaces the
+// default list of that column.
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColumnAliases
+{
+    pub order_date: Vec<String>,
+    pub order_number: Vec<String>,
+    pub shipping: Vec<String>,
+    pub tax: Vec<String>,
+    pub total: Vec<String>,
+    pub title: Vec<String>,
+    pub category: Vec<String>,
+    pub quantity: Vec<String>,
+    pub item_subtotal: Vec<String>,
+    pub item_tax: Vec<String>,
+    pub item_total: Vec<String>,
+    pub refund_date: Vec<String>,
+    pub refund_amount: Vec<String>,
+    pub refund_tax: Vec<String>,
+    pub refund_shipping: Vec<String>,
+    pub payment: Vec<String>,
+    pub promotion: Vec<String>,
+    pub gift_card: Vec<String>,
+    pub bank_date: Vec<String>,
+    pub bank_amount: Vec<String>,
+    pub bank_description: Vec<String>,
+    pub shipment_date: Vec<String>,
+}
+
+fn strings(names: &[&str]) -> Vec<String>
+{
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+impl Default for ColumnAliases
+{
+    fn default() -> Self
+    {
+        Self {
+            order_date: strings(&["Order Date"]),
+            order_number: strings(&["Order ID", "Order Number"]),
+            shipping: strings(&["Shipping Charge"]),
+            tax: strings(&["Tax Charged"]),
+            total: strings(&["Total Charged"]),
+            title: strings(&["Title"]),
+            category: strings(&["Category"]),
+            quantity: strings(&["Quantity"]),
+            item_subtotal: strings(&["Item Subtotal"]),
+            item_tax: strings(&["Item Subtotal Tax"]),
+            item_total: strings(&["Item Total"]),
+            refund_date: strings(&["Refund Date"]),
+            refund_amount: strings(&["Refund Amount"]),
+            refund_tax: strings(&["Refund Tax Amount"]),
+            refund_shipping: strings(&["Refund Shipping Amount"]),
+            payment: strings(&["Payment Instrument Type"]),
+            promotion: strings(&["Total Promotions"]),
+            gift_card: strings(&["Gift Card Amount", "Gift Certificate Amount"]),
+            bank_date: strings(&["Transaction Date", "Posting Date", "Date"]),
+            bank_amount: strings(&["Amount", "Debit"]),
+            bank_description: strings(&["Description", "Payee", "Memo"]),
+            shipment_date: strings(&["Shipment Date"]),
+        }
+    }
+}
+
+impl ColumnAliases
+{
+    pub fn aliases(&self, column: Column) -> &[String]
+    {
+        match column
+        {
+            Column::OrderDate => &self.order_date,
+            Column::OrderNumber => &self.order_number,
+            Column::Shipping => &self.shipping,
+            Column::Tax => &self.tax,
+            Column::Total => &self.total,
+            Column::Title => &self.title,
+            Column::Category => &self.category,
+            Column::Quantity => &self.quantity,
+            Column::ItemSubtotal => &self.item_subtotal,
+            Column::ItemTax => &self.item_tax,
+            Column::ItemTotal => &self.item_total,
+            Column::RefundDate => &self.refund_date,
+            Column::RefundAmount => &self.refund_amount,
+            Column::RefundTax => &self.refund_tax,
+            Column::RefundShipping => &self.refund_shipping,
+            Column::Payment => &self.payment,
+            Column::Promotion => &self.promotion,
+            Column::GiftCard => &self.gift_card,
+            Column::BankDate => &self.bank_date,
+            Column::BankAmount => &self.bank_amount,
+            Column::BankDescription => &self.bank_description,
+            Column::ShipmentDate => &self.shipment_date,
+        }
+    }
+}
+
+// Where each column is in a CSV file.
+pub struct ColumnMap
+{
+    indices: HashMap<Column, usize>,
+    aliases: ColumnAliases,
+}
+
+impl ColumnMap
+{
+    // Locate all the known columns in the CSV header. Columns that are
+    // not found are simply absent from the map.
+    pub fn fromHeader(header: &csv::StringRecord, aliases: &ColumnAliases)
+                      -> Self
+    {
+        let header: Vec<String> = header.iter()
+            .map(|name| name.trim().to_lowercase()).collect();
+        let mut indices = HashMap::new();
+        for column in Column::ALL
+        {
+            let index = aliases.aliases(*column).iter().find_map(|name| {
+                let name = name.trim().to_lowercase();
+                header.iter().position(|h| h == &name)
+            });
+            if let Some(i) = index
+            {
+                indices.insert(*column, i);
+            }
+        }
+        Self { indices, aliases: aliases.clone() }
+    }
+
+    pub fn has(&self, column: Column) -> bool
+    {
+        self.indices.contains_key(&column)
+    }
+
+    // Make sure all the `required` columns are in the file. The error
+    // lists all the missing ones.
+    pub fn require(&self, required: &[Column]) -> Result<(), Error>
+    {
+        let missing: Vec<String> = required.iter()
+            .filter(|column| !self.has(**column))
+            .map(|column| format!(
+                "{} (header {})", column.name(),
+                self.aliases.aliases(*column).iter()
+                    .map(|n| format!("\"{}\"", n))
+                    .collect::<Vec<_>>().join(" or ")))
+            .collect();
+
+        if missing.is_empty()
+        {
+            Ok(())
+        }
+        else
+        {
+            Err(rterr!("Missing columns in CSV: {}", missing.join(", ")))
+        }
+    }
+
+    // Get the value of `column` in `row`. Return None if the column
+    // is not in the file, or the row is too short.
+    pub fn get<'a>(&self, row: &'a csv::StringRecord, column: Column)
+                   -> Option<&'a str>
+    {
+        self.indices.get(&column).and_then(|i| row.get(*i))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn testAliases()
+    {
+        let config: Config = toml::from_str(
+            "[columns]\norder_date = [\"Date of Order\"]\n").unwrap();
+        let header = csv::StringRecord::from(
+            vec![" date of order ", "ORDER ID", "Order Date", "Total Charged "]);
+        let map = ColumnMap::fromHeader(&header, &config.columns);
+        let row = csv::StringRecord::from(
+            vec!["01/05/21", "111-0000001-0000001", "02/05/21", "$1.00"]);
+
+        // The configured name replaces the default “Order Date”.
+        assert_eq!(map.get(&row, Column::OrderDate), Some("01/05/21"));
+        assert_eq!(map.get(&row, Column::OrderNumber), Some("111-0000001-0000001"));
+        assert_eq!(map.get(&row, Column::Total), Some("$1.00"));
+        assert!(!map.has(Column::Tax));
+        assert!(map.require(&[Column::OrderDate, Column::Total]).is_ok());
+        assert!(map.require(&[Column::Tax]).is_err());
+
+        let header = csv::StringRecord::from(vec!["Order Date", "Order ID"]);
+        let map = ColumnMap::fromHeader(&header, &config.columns);
+        assert!(!map.has(Column::OrderDate));
+    }
+}
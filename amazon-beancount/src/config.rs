@@ -1,9 +1,68 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::error::Error;
+use crate::columns::ColumnAliases;
+use crate::rules::{self, Rule};
+use crate::marketplace::Marketplace;
+use crate::payment::PaymentAccount;
+
+const CONF_FILE: &str = "amazon-beancount.toml";
+
+// Where tax or shipping is posted.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Allocation
+{
+    // To its own account, account_tax or account_shipping.
+    #[default]
+    Separate,
+    // Into the expense postings, in proportion to their amounts.
+    Proportional,
+}
+
+// The sign of the charges in the bank statement. Amounts of the other
+// sign are credits, like refunds, and are not reconciled.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ChargeSign
+{
+    // Whichever sign most of the amounts in the file have.
+    #[default]
+    Auto,
+    Negative,
+    Positive,
+}
+
+// Any field missing from the config file takes its value from
+// Config::default().
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config
 {
     pub account_credit: String,
     pub account_tax: String,
     pub account_shipping: String,
     pub account_expense: String,
+    pub account_gift_card: String,
+    pub account_promotion: String,
+    pub tax_allocation: Allocation,
+    pub shipping_allocation: Allocation,
+    pub columns: ColumnAliases,
+    pub rules: Vec<Rule>,
+    pub marketplace: Marketplace,
+    // The credit account of each payment instrument. Instruments that
+    // are not here use account_credit.
+    pub payment: Vec<PaymentAccount>,
+    // Only the charges in the bank statement whose description matches
+    // this are reconciled.
+    #[serde(deserialize_with = "rules::deserializeRegex")]
+    pub bank_filter: Regex,
+    pub bank_charge_sign: ChargeSign,
 }
 
 impl Default for Config
@@ -15,6 +74,105 @@ impl Default for Config
             account_tax: "Expenses:Taxes:Consumer".to_owned(),
             account_shipping: "Expenses:Shipping".to_owned(),
             account_expense: "Expenses:Misc".to_owned(),
+            account_gift_card: "Assets:GiftCard:Amazon".to_owned(),
+            account_promotion: "Income:Discount:Amazon".to_owned(),
+            tax_allocation: Allocation::default(),
+            shipping_allocation: Allocation::default(),
+            columns: ColumnAliases::default(),
+            rules: Vec::new(),
+            marketplace: Marketplace::default(),
+            payment: Vec::new(),
+            bank_filter: Regex::new("(?i)amazon|amzn").unwrap(),
+            bank_charge_sign: ChargeSign::default(),
+        }
+    }
+}
+
+impl Config
+{
+    pub fn fromFile(filename: &Path) -> Result<Self, Error>
+    {
+        let contents = fs::read_to_string(filename).map_err(
+            |e| rterr!("Failed to read config file {}: {}",
+                       filename.to_string_lossy(), e))?;
+        toml::from_str(&contents).map_err(
+            |e| rterr!("Failed to parse config file {}: {}",
+                       filename.to_string_lossy(), e))
+    }
+
+    // Load the config from `filename` if given. Otherwise look for it
+    // in the XDG config dir, and fall back to the default config if it
+    // is not there.
+    pub fn load(filename: Option<&Path>) -> Result<Self, Error>
+    {
+        if let Some(f) = filename
+        {
+            return Self::fromFile(f);
         }
+        match findConfigFile()
+        {
+            Some(f) => Self::fromFile(&f),
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+// Return $XDG_CONFIG_HOME/amazon-beancount.toml, or
+// ~/.config/amazon-beancount.toml if XDG_CONFIG_HOME is not set, if
+// the file exists.
+fn findConfigFile() -> Option<PathBuf>
+{
+    let dir = match env::var("XDG_CONFIG_HOME")
+    {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => match env::var("HOME")
+        {
+            Ok(home) if !home.is_empty() => PathBuf::from(home).join(".config"),
+            _ => return None,
+        },
+    };
+    let path = dir.join(CONF_FILE);
+    if path.exists()
+    {
+        Some(path)
+    }
+    else
+    {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn testDefaults()
+    {
+        let config: Config = toml::from_str(
+            r#"account_credit = "Liabilities:Credit:Visa""#).unwrap();
+        let default = Config::default();
+        assert_eq!(config.account_credit, "Liabilities:Credit:Visa");
+        assert_eq!(config.account_tax, default.account_tax);
+        assert_eq!(config.account_expense, default.account_expense);
+        assert_eq!(config.columns.order_date, default.columns.order_date);
+        assert_eq!(config.marketplace, default.marketplace);
+        assert_eq!(config.tax_allocation, Allocation::Separate);
+        assert!(config.rules.is_empty());
+        assert_eq!(config.bank_filter.as_str(), default.bank_filter.as_str());
+        assert_eq!(config.bank_charge_sign, ChargeSign::Auto);
+    }
+
+    #[test]
+    fn testUnknownKeys()
+    {
+        assert!(toml::from_str::<Config>(r#"acount_credit = "Assets:Cash""#).is_err());
+        assert!(toml::from_str::<Config>(
+            "[columns]\norder_dates = [\"Date\"]\n").is_err());
+        assert!(toml::from_str::<Config>(
+            "[[rules]]\ntitel = \"Book\"\naccount = \"Expenses:Books\"\n").is_err());
+        assert!(toml::from_str::<Config>(
+            "[[rules]]\ntitle = \"Book\"\naccount = \"Expenses:Books\"\n").is_ok());
     }
 }
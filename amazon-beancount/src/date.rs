@@ -0,0 +1,195 @@
+use chrono::NaiveDate;
+
+use crate::error::Error;
+
+// The date formats that have appeared in Amazon reports.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DateFormat
+{
+    // “01/31/21”, in older reports.
+    ShortSlash,
+    // “01/31/2021”.
+    LongSlash,
+    // “2021-01-31”, optionally followed by a time like
+    // “2021-01-31T08:00:00Z”.
+    Iso,
+}
+
+impl DateFormat
+{
+    // Guess the format from a sample date string.
+    pub fn detect(date: &str) -> Option<Self>
+    {
+        let date = date.trim();
+        if date.contains('-')
+        {
+            return Some(DateFormat::Iso);
+        }
+        let parts: Vec<&str> = date.split('/').collect();
+        if parts.len() != 3
+        {
+            return None;
+        }
+        match parts[2].len()
+        {
+            2 => Some(DateFormat::ShortSlash),
+            4 => Some(DateFormat::LongSlash),
+            _ => None,
+        }
+    }
+
+    // Parse a date string in this format. Return None if the string
+    // is not in this format, or is not a valid date.
+    pub fn parse(&self, date: &str) -> Option<NaiveDate>
+    {
+        let date = date.trim();
+        match self
+        {
+            DateFormat::ShortSlash =>
+            {
+                let parts: Vec<&str> = date.split('/').collect();
+                if parts.len() != 3 || parts[2].len() != 2
+                {
+                    return None;
+                }
+                NaiveDate::from_ymd_opt(2000 + parts[2].parse::<i32>().ok()?,
+                                        parts[0].parse().ok()?,
+                                        parts[1].parse().ok()?)
+            },
+            DateFormat::LongSlash =>
+            {
+                if date.rsplit('/').next()?.len() != 4
+                {
+                    return None;
+                }
+                NaiveDate::parse_from_str(date, "%m/%d/%Y").ok()
+            },
+            DateFormat::Iso =>
+            {
+                let day = date.split('T').next()?;
+                NaiveDate::parse_from_str(day, "%Y-%m-%d").ok()
+            },
+        }
+    }
+}
+
+// Parse the dates in a CSV file. The format is decided by the first
+// date, and all dates in the file should have the same format.
+#[derive(Default)]
+pub struct DateParser
+{
+    format: Option<DateFormat>,
+}
+
+impl DateParser
+{
+    pub fn new() -> Self
+    {
+        Self { format: None }
+    }
+
+    // Parse `date`, which is at line `row` of the file.
+    pub fn parse(&mut self, date: &str, row: u64) -> Result<NaiveDate, Error>
+    {
+        let error = || Error::DateError { row, text: date.to_owned() };
+        let format = match self.format
+        {
+            Some(f) => f,
+            None => {
+                let f = DateFormat::detect(date).ok_or_else(error)?;
+                self.format = Some(f);
+                f
+            },
+        };
+        format.parse(date).ok_or_else(error)
+    }
+
+    // Like parse(), but return None if `date` does not look like a date
+    // at all, like an empty string or “Not yet shipped”.
+    pub fn parseOptional(&mut self, date: &str, row: u64)
+                         -> Result<Option<NaiveDate>, Error>
+    {
+        if DateFormat::detect(date).is_none()
+        {
+            Ok(None)
+        }
+        else
+        {
+            self.parse(date, row).map(Some)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn ymd(y: i32, m: u32, d: u32) -> NaiveDate
+    {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn testDetectAndParse()
+    {
+        assert_eq!(DateFormat::detect("01/31/21"), Some(DateFormat::ShortSlash));
+        assert_eq!(DateFormat::detect("01/31/2021"), Some(DateFormat::LongSlash));
+        assert_eq!(DateFormat::detect("2021-01-31T08:00:00Z"), Some(DateFormat::Iso));
+        assert_eq!(DateFormat::detect("Not yet shipped"), None);
+
+        assert_eq!(DateFormat::ShortSlash.parse("1/31/21"), Some(ymd(2021, 1, 31)));
+        assert_eq!(DateFormat::LongSlash.parse("01/31/2021"), Some(ymd(2021, 1, 31)));
+        assert_eq!(DateFormat::Iso.parse("2021-01-31T08:00:00Z"),
+                   Some(ymd(2021, 1, 31)));
+        assert_eq!(DateFormat::ShortSlash.parse("02/30/21"), None);
+        assert_eq!(DateFormat::LongSlash.parse("01/31/21"), None);
+    }
+
+    #[test]
+    fn testDateParser()
+    {
+        let mut parser = DateParser::new();
+        assert_eq!(parser.parse("2021-01-31", 2), Ok(ymd(2021, 1, 31)));
+        // The format is fixed by the first date.
+        assert_eq!(parser.parse("01/31/2021", 3),
+                   Err(Error::DateError { row: 3, text: String::from("01/31/2021") }));
+        assert_eq!(parser.parseOptional("", 4), Ok(None));
+        assert_eq!(parser.parseOptional("Not yet shipped", 5), Ok(None));
+        assert_eq!(parser.parseOptional("2021-02-01", 6), Ok(Some(ymd(2021, 2, 1))));
+
+        let mut parser = DateParser::new();
+        assert_eq!(parser.parse("31/01/2021", 2),
+                   Err(Error::DateError { row: 2, text: String::from("31/01/2021") }));
+    }
+
+    #[test]
+    fn testYearDigits()
+    {
+        // A 2-digit year is never read as a year in the first century.
+        let mut parser = DateParser::new();
+        assert_eq!(parser.parse("01/31/2021", 2), Ok(ymd(2021, 1, 31)));
+        assert_eq!(parser.parse("01/31/21", 3),
+                   Err(Error::DateError { row: 3, text: String::from("01/31/21") }));
+
+        let mut parser = DateParser::new();
+        assert_eq!(parser.parse("01/31/21", 2), Ok(ymd(2021, 1, 31)));
+        assert_eq!(parser.parse("02/01/2021", 3),
+                   Err(Error::DateError { row: 3, text: String::from("02/01/2021") }));
+    }
+
+    #[test]
+    fn testIsoAndEmpty()
+    {
+        let mut parser = DateParser::new();
+        assert_eq!(parser.parse("2021-01-31T08:00:00Z", 2), Ok(ymd(2021, 1, 31)));
+        assert_eq!(parser.parse("2021-02-01", 3), Ok(ymd(2021, 2, 1)));
+        assert_eq!(parser.parse("", 4),
+                   Err(Error::DateError { row: 4, text: String::new() }));
+
+        // An empty first date does not fix the format.
+        let mut parser = DateParser::new();
+        assert!(parser.parse("", 2).is_err());
+        assert_eq!(parser.parse("01/31/21", 3), Ok(ymd(2021, 1, 31)));
+    }
+}
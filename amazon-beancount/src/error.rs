@@ -32,6 +32,8 @@ macro_rules! rterr
 pub enum Error
 {
     RuntimeError(String),
+    // An unparsable date at a row (line number in the file) of a CSV.
+    DateError { row: u64, text: String },
 }
 
 impl fmt::Display for Error
@@ -41,6 +43,8 @@ impl fmt::Display for Error
         match self
         {
             Error::RuntimeError(msg) => write!(f, "Runtime error: {}", msg),
+            Error::DateError { row, text } =>
+                write!(f, "Invalid date '{}' at row {}", text, row),
         }
     }
 }
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::io::Read;
+
+use chrono::NaiveDate;
+
+use crate::error::Error;
+use crate::config::Config;
+use crate::order_record::Order;
+
+// An order whose rows in the report have different dates.
+pub struct DateConflict
+{
+    pub order_number: String,
+    // All the dates, in the order they are seen. The first one is the
+    // date of the order.
+    pub dates: Vec<NaiveDate>,
+}
+
+// Combine the rows of the same order into one order, or one order per
+// shipment if the rows have shipment dates. The rows of an order do not
+// have to be consecutive, and the orders are kept in the order they are
+// first seen.
+#[derive(Default)]
+pub struct OrderGroups
+{
+    orders: Vec<Order>,
+    index: HashMap<(String, Option<NaiveDate>), usize>,
+    // Distinct order dates of each order number, and the order numbers
+    // in the order they are first seen.
+    dates: HashMap<String, Vec<NaiveDate>>,
+    order_numbers: Vec<String>,
+}
+
+impl OrderGroups
+{
+    pub fn new() -> Self
+    {
+        Self {
+            orders: Vec::new(),
+            index: HashMap::new(),
+            dates: HashMap::new(),
+            order_numbers: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, order: Order)
+    {
+        match self.dates.get_mut(&order.order_number)
+        {
+            Some(dates) => if !dates.contains(&order.date)
+            {
+                dates.push(order.date);
+            },
+            None => {
+                self.dates.insert(order.order_number.clone(), vec![order.date]);
+                self.order_numbers.push(order.order_number.clone());
+            },
+        }
+
+        let key = (order.order_number.clone(), order.shipment_date);
+        if let Some(i) = self.index.get(&key).copied()
+        {
+            self.orders[i] += order;
+        }
+        else
+        {
+            self.index.insert(key, self.orders.len());
+            self.orders.push(order);
+        }
+    }
+
+    // Return the combined orders, and the orders with conflicting
+    // dates.
+    pub fn finish(mut self) -> (Vec<Order>, Vec<DateConflict>)
+    {
+        let mut conflicts = Vec::new();
+        for order_number in self.order_numbers
+        {
+            if let Some(dates) = self.dates.remove(&order_number)
+            {
+                if dates.len() > 1
+                {
+                    conflicts.push(DateConflict { order_number, dates });
+                }
+            }
+        }
+        (self.orders, conflicts)
+    }
+}
+
+// Read an orders or items report from `reader`, and combine its rows
+// into orders with OrderGroups.
+pub fn readOrders<R: Read>(reader: R, config: &Config)
+                           -> Result<(Vec<Order>, Vec<DateConflict>), Error>
+{
+    let mut groups = OrderGroups::new();
+    Order::readCSVFrom(reader, config, |order| groups.add(order))?;
+    Ok(groups.finish())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn ymd(y: i32, m: u32, d: u32) -> NaiveDate
+    {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn group(data: &str) -> (Vec<Order>, Vec<DateConflict>)
+    {
+        readOrders(data.as_bytes(), &Config::default()).unwrap()
+    }
+
+    const HEADER: &str = "Order Date,Order ID,Shipment Date,Shipping Charge,\
+                          Tax Charged,Total Charged";
+
+    #[test]
+    fn testInterleaved()
+    {
+        let data = format!("{}\n\
+                            01/05/21,3,,$0,$0,$1\n\
+                            01/05/21,1,,$0,$0,$2\n\
+                            01/05/21,3,,$0,$0,$4\n\
+                            01/06/21,2,,$0,$0,$8\n\
+                            01/05/21,1,,$0,$0,$16\n", HEADER);
+        let (orders, conflicts) = group(&data);
+        assert!(conflicts.is_empty());
+        // In the order they are first seen, not by order number.
+        let summary: Vec<(&str, String)> = orders.iter()
+            .map(|o| (o.order_number.as_str(), o.total().to_string())).collect();
+        assert_eq!(summary, vec![("3", String::from("5")), ("1", String::from("18")),
+                                 ("2", String::from("8"))]);
+    }
+
+    #[test]
+    fn testShipments()
+    {
+        let data = format!("{}\n\
+                            01/05/21,1,01/06/21,$0,$0,$1\n\
+                            01/05/21,2,01/06/21,$0,$0,$2\n\
+                            01/05/21,1,01/08/21,$0,$0,$4\n\
+                            01/05/21,1,01/06/21,$0,$0,$8\n", HEADER);
+        let (orders, _) = group(&data);
+        let summary: Vec<(&str, Option<NaiveDate>, String)> = orders.iter()
+            .map(|o| (o.order_number.as_str(), o.shipment_date, o.total().to_string()))
+            .collect();
+        assert_eq!(summary, vec![("1", Some(ymd(2021, 1, 6)), String::from("9")),
+                                 ("2", Some(ymd(2021, 1, 6)), String::from("2")),
+                                 ("1", Some(ymd(2021, 1, 8)), String::from("4"))]);
+    }
+
+    #[test]
+    fn testDateConflict()
+    {
+        let data = format!("{}\n\
+                            01/07/21,2,,$0,$0,$1\n\
+                            01/05/21,1,,$0,$0,$1\n\
+                            01/05/21,2,,$0,$0,$1\n\
+                            01/05/21,1,,$0,$0,$1\n\
+                            01/09/21,2,,$0,$0,$1\n", HEADER);
+        let (orders, conflicts) = group(&data);
+        assert_eq!(orders.len(), 2);
+        // The date of an order is the date of its first row.
+        assert_eq!(orders[0].date, ymd(2021, 1, 7));
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].order_number, "2");
+        assert_eq!(conflicts[0].dates,
+                   vec![ymd(2021, 1, 7), ymd(2021, 1, 5), ymd(2021, 1, 9)]);
+    }
+}
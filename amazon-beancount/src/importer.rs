@@ -0,0 +1,412 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+use crate::config::Config;
+use crate::columns::ColumnMap;
+use crate::grouping;
+use crate::ledger::Ledger;
+use crate::order_record::{Order, Report};
+use crate::refund::Refund;
+use crate::transaction::{MetaValue, Transaction};
+
+// The kinds of file that the importer understands.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FileKind
+{
+    // An orders or items report.
+    Orders(Report),
+    Refunds,
+}
+
+impl FileKind
+{
+    pub fn name(&self) -> &'static str
+    {
+        match self
+        {
+            FileKind::Orders(Report::Orders) => "orders report",
+            FileKind::Orders(Report::Items) => "items report",
+            FileKind::Refunds => "refunds report",
+        }
+    }
+}
+
+// The transactions extracted from a file.
+pub struct Extracted
+{
+    pub path: PathBuf,
+    pub kind: FileKind,
+    pub transactions: Vec<Transaction>,
+    // Problems that the user should check in the transactions.
+    pub warnings: Vec<String>,
+}
+
+// Decide what kind of report `path` is from its header. Return None
+// if it is not a CSV file, or does not have the columns of any report.
+pub fn identify(path: &Path, config: &Config) -> Option<FileKind>
+{
+    if !path.extension().is_some_and(|e| e.eq_ignore_ascii_case("csv"))
+    {
+        return None;
+    }
+    let mut csv_reader = csv::Reader::from_path(path).ok()?;
+    let header = csv_reader.headers().ok()?.clone();
+    let columns = ColumnMap::fromHeader(&header, &config.columns);
+    // A refunds report also has titles and categories, so look for it
+    // first.
+    if columns.require(Refund::COLUMNS).is_ok()
+    {
+        return Some(FileKind::Refunds);
+    }
+    let report = Report::detect(&columns);
+    if columns.require(report.requiredColumns()).is_ok()
+    {
+        Some(FileKind::Orders(report))
+    }
+    else
+    {
+        None
+    }
+}
+
+// All the files under `dir`, recursively, sorted by path.
+pub fn findFiles(dir: &Path) -> Result<Vec<PathBuf>, Error>
+{
+    let mut files = Vec::new();
+    let entries = fs::read_dir(dir).map_err(
+        |e| rterr!("Failed to read directory {}: {}", dir.to_string_lossy(), e))?;
+    for entry in entries
+    {
+        let path = entry.map_err(
+            |e| rterr!("Failed to read directory {}: {}",
+                       dir.to_string_lossy(), e))?.path();
+        if path.is_dir()
+        {
+            files.extend(findFiles(&path)?);
+        }
+        else
+        {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// A key that identifies the transaction of an order (or one shipment of
+// it) across imports, like “amazon:111-1234567-1234567:2021-01-31”.
+pub fn orderKey(order: &Order) -> String
+{
+    match order.shipment_date
+    {
+        Some(date) => format!("amazon:{}:{}", order.order_number,
+                              date.format("%F")),
+        None => format!("amazon:{}", order.order_number),
+    }
+}
+
+pub fn refundKey(refund: &Refund) -> String
+{
+    format!("amazon:{}:refund:{}", refund.order_number, refund.date.format("%F"))
+}
+
+// Add the dedup key and the provenance to a transaction.
+fn addImportMeta(t: &mut Transaction, key: String, path: &Path, line: u64)
+{
+    t.meta.push(("import-key".to_owned(), MetaValue::Text(key)));
+    t.meta.push(("source-file".to_owned(),
+                 MetaValue::Text(path.to_string_lossy().into_owned())));
+    t.meta.push(("source-line".to_owned(), MetaValue::Plain(line.to_string())));
+}
+
+// Open a report. Unlike Order::readCSV(), the errors do not have the
+// path, as they are reported along with it.
+fn open(path: &Path) -> Result<fs::File, Error>
+{
+    fs::File::open(path).map_err(|e| rterr!("Failed to read CSV file: {}", e))
+}
+
+// What has been extracted so far in a run, so that an order in two
+// reports, like the orders report and the items report of the same
+// period, is only extracted once.
+#[derive(Default)]
+struct Emitted
+{
+    keys: HashSet<String>,
+    // The file that each order number is first extracted from.
+    order_files: HashMap<String, PathBuf>,
+}
+
+impl Emitted
+{
+    // Whether an order from `path` is already extracted, as the same
+    // shipment or from another report.
+    fn hasOrder(&self, order: &Order, key: &str, path: &Path) -> bool
+    {
+        self.keys.contains(key) || self.order_files.get(&order.order_number)
+            .is_some_and(|p| p != path)
+    }
+
+    fn addOrder(&mut self, order: &Order, key: &str, path: &Path)
+    {
+        self.keys.insert(key.to_owned());
+        self.order_files.entry(order.order_number.clone())
+            .or_insert_with(|| path.to_owned());
+    }
+}
+
+// Extract the transactions of the `orders` in the report at `path`.
+// Those that are already in `ledger` or in `emitted` are left out.
+fn extractOrders(path: &Path, kind: FileKind, orders: &[Order], config: &Config,
+                 ledger: Option<&Ledger>, emitted: &mut Emitted) -> Extracted
+{
+    let mut transactions = Vec::new();
+    let mut warnings = Vec::new();
+    for order in orders
+    {
+        let key = orderKey(order);
+        if emitted.hasOrder(order, &key, path) || ledger.is_some_and(
+            |l| l.import_keys.contains(&key) ||
+                l.hasOrder(&order.order_number, order.shipment_date))
+        {
+            continue;
+        }
+        emitted.addOrder(order, &key, path);
+        warnings.extend(order.giftCardWarning(config));
+        let mut t = order.transaction(config);
+        addImportMeta(&mut t, key, path, order.line);
+        transactions.push(t);
+    }
+    Extracted { path: path.to_owned(), kind, transactions, warnings }
+}
+
+// Like extractOrders(), but for the refunds report at `path`. Refunds
+// are credited to the payment instruments of their orders in `orders`.
+fn extractRefunds(path: &Path, refunds: Vec<Refund>, config: &Config,
+                  ledger: Option<&Ledger>, orders: &[Order], emitted: &mut Emitted)
+                  -> Extracted
+{
+    let mut transactions = Vec::new();
+    for mut refund in refunds
+    {
+        refund.matchOrder(orders);
+        let key = refundKey(&refund);
+        if emitted.keys.contains(&key) || ledger.is_some_and(
+            |l| l.import_keys.contains(&key) ||
+                l.hasRefund(&refund.order_number, refund.date))
+        {
+            continue;
+        }
+        emitted.keys.insert(key.clone());
+        let mut t = refund.transaction(config);
+        addImportMeta(&mut t, key, path, refund.line);
+        transactions.push(t);
+    }
+    Extracted { path: path.to_owned(), kind: FileKind::Refunds, transactions,
+                warnings: Vec::new() }
+}
+
+// The order in which the kinds of reports are extracted. Orders reports
+// come before items reports, so that an order in both is extracted from
+// the orders report, which has the shipments and the payment
+// instruments. Refunds come last, when all the orders are known.
+fn rank(kind: FileKind) -> u8
+{
+    match kind
+    {
+        FileKind::Orders(Report::Orders) => 0,
+        FileKind::Orders(Report::Items) => 1,
+        FileKind::Refunds => 2,
+    }
+}
+
+// Identify and extract all the files under `dir`, and return the
+// results sorted by path. The files that are not understood, or fail to
+// read, are reported with `skipped` along with the reason. An order in
+// more than one report is extracted from the orders report if there is
+// one, and otherwise from the first one by path. A refund in more than
+// one file is extracted from the first one.
+pub fn extractDir<F>(dir: &Path, config: &Config, ledger: Option<&Ledger>,
+                     mut skipped: F) -> Result<Vec<Extracted>, Error>
+    where F: FnMut(&Path, &str)
+{
+    let mut files = Vec::new();
+    for path in findFiles(dir)?
+    {
+        match identify(&path, config)
+        {
+            Some(kind) => files.push((path, kind)),
+            None => skipped(&path, "not an Amazon report"),
+        }
+    }
+    files.sort_by_key(|(_, kind)| rank(*kind));
+
+    let mut orders = Vec::new();
+    let mut emitted = Emitted::default();
+    let mut result = Vec::new();
+    for (path, kind) in files
+    {
+        let extracted = match kind
+        {
+            FileKind::Orders(_) => open(&path)
+                .and_then(|file| grouping::readOrders(file, config))
+                .map(|(file_orders, _)| {
+                    let e = extractOrders(&path, kind, &file_orders, config, ledger,
+                                          &mut emitted);
+                    orders.extend(file_orders);
+                    e
+                }),
+            FileKind::Refunds => open(&path)
+                .and_then(|file| Refund::fromCSVFrom(file, config))
+                .map(|refunds| extractRefunds(&path, refunds, config, ledger,
+                                              &orders, &mut emitted)),
+        };
+        match extracted
+        {
+            Ok(e) => result.push(e),
+            Err(e) => skipped(&path, &e.to_string()),
+        }
+    }
+    result.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // A temporary directory with the test reports, which is removed
+    // when dropped.
+    struct TestDir(PathBuf);
+
+    impl TestDir
+    {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self
+        {
+            let dir = std::env::temp_dir().join(
+                format!("amazon-beancount-{}-{}", name, std::process::id()));
+            fs::create_dir_all(dir.join("sub")).unwrap();
+            for (filename, contents) in files
+            {
+                fs::write(dir.join(filename), contents).unwrap();
+            }
+            Self(dir)
+        }
+    }
+
+    impl Drop for TestDir
+    {
+        fn drop(&mut self)
+        {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    const REFUNDS: &str = "\
+Order ID,Title,Category,Refund Date,Refund Amount,Refund Tax Amount
+111-0000002-0000002,Coffee,Grocery,01/20/21,$10.00,$0.00
+";
+
+    fn importKey(t: &Transaction) -> &str
+    {
+        match &t.meta.iter().find(|(k, _)| k == "import-key").unwrap().1
+        {
+            MetaValue::Text(s) | MetaValue::Plain(s) => s,
+        }
+    }
+
+    #[test]
+    fn testIdentify()
+    {
+        let dir = TestDir::new("identify", &[
+            ("orders.csv", include_str!("../testdata/orders.csv")),
+            ("sub/items.CSV", include_str!("../testdata/items.csv")),
+            ("refunds.csv", REFUNDS),
+            ("orders.txt", include_str!("../testdata/orders.csv")),
+            ("bank.csv", "Date,Description,Amount\n")]);
+        let config = Config::default();
+        let kind = |name: &str| identify(&dir.0.join(name), &config);
+        assert_eq!(kind("orders.csv"), Some(FileKind::Orders(Report::Orders)));
+        assert_eq!(kind("sub/items.CSV"), Some(FileKind::Orders(Report::Items)));
+        assert_eq!(kind("refunds.csv"), Some(FileKind::Refunds));
+        assert_eq!(kind("orders.txt"), None);
+        assert_eq!(kind("bank.csv"), None);
+
+        let files = findFiles(&dir.0).unwrap();
+        assert_eq!(files.len(), 5);
+        assert!(files.contains(&dir.0.join("sub").join("items.CSV")));
+    }
+
+    #[test]
+    fn testExtract()
+    {
+        let dir = TestDir::new("extract", &[
+            ("orders.csv", include_str!("../testdata/orders.csv")),
+            ("refunds.csv", REFUNDS),
+            ("notes.txt", "")]);
+        let mut skipped = Vec::new();
+        let extracted = extractDir(&dir.0, &Config::default(), None,
+                                   |path, _| skipped.push(path.to_owned())).unwrap();
+        assert_eq!(skipped, vec![dir.0.join("notes.txt")]);
+        assert_eq!(extracted.len(), 2);
+
+        assert_eq!(extracted[0].kind, FileKind::Orders(Report::Orders));
+        let keys: Vec<&str> = extracted[0].transactions.iter().map(importKey).collect();
+        assert_eq!(keys, vec!["amazon:111-0000001-0000001:2021-01-06",
+                              "amazon:111-0000001-0000001:2021-01-08",
+                              "amazon:111-0000002-0000002"]);
+        assert_eq!(extracted[1].kind, FileKind::Refunds);
+        assert_eq!(importKey(&extracted[1].transactions[0]),
+                   "amazon:111-0000002-0000002:refund:2021-01-20");
+
+        let ledger = Ledger::fromText(
+            "2021-01-08 * \"Amazon\" \"\"\n  import-key: \"amazon:111-0000002-0000002\"\n");
+        let extracted = extractDir(&dir.0, &Config::default(), Some(&ledger),
+                                   |_, _| ()).unwrap();
+        assert_eq!(extracted[0].transactions.len(), 2);
+    }
+
+    #[test]
+    fn testExtractTwoReports()
+    {
+        // The items report and the orders report have the same orders,
+        // which are extracted from the orders report.
+        let dir = TestDir::new("two-reports", &[
+            ("items.csv", include_str!("../testdata/items.csv")),
+            ("orders.csv", include_str!("../testdata/orders.csv")),
+            ("refunds.csv", REFUNDS),
+            ("sub/refunds.csv", REFUNDS)]);
+        let extracted = extractDir(&dir.0, &Config::default(), None, |_, _| ())
+            .unwrap();
+        let paths: Vec<&Path> = extracted.iter().map(|e| e.path.as_path()).collect();
+        assert_eq!(paths, vec![dir.0.join("items.csv"), dir.0.join("orders.csv"),
+                               dir.0.join("refunds.csv"),
+                               dir.0.join("sub").join("refunds.csv")]);
+        let counts: Vec<usize> = extracted.iter().map(|e| e.transactions.len())
+            .collect();
+        assert_eq!(counts, vec![0, 3, 1, 0]);
+    }
+
+    #[test]
+    fn testExtractBadReport()
+    {
+        let dir = TestDir::new("bad-report", &[
+            ("bad.csv", "Order Date,Order ID,Shipping Charge,Tax Charged,Total Charged
+yesterday,111-0000003-0000003,$0.00,$0.00,$1.00
+"),
+            ("orders.csv", include_str!("../testdata/orders.csv"))]);
+        let mut skipped = Vec::new();
+        let extracted = extractDir(&dir.0, &Config::default(), None,
+                                   |path, reason| skipped.push((path.to_owned(),
+                                                                reason.to_owned())))
+            .unwrap();
+        assert_eq!(extracted.len(), 1);
+        assert_eq!(extracted[0].transactions.len(), 3);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].0, dir.0.join("bad.csv"));
+        assert!(skipped[0].1.contains("yesterday"));
+    }
+}
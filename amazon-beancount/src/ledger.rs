@@ -0,0 +1,256 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::path::Path;
+
+use chrono::NaiveDate;
+
+use crate::error::Error;
+
+// What we need to know about an existing ledger. Beancount, ledger and
+// hledger files are all understood.
+pub struct Ledger
+{
+    // The values of all the “order-number” metadata of whole orders in
+    // the ledger.
+    pub order_numbers: HashSet<String>,
+    // The order numbers and shipment dates of orders that are recorded
+    // by shipment.
+    pub shipments: HashSet<(String, NaiveDate)>,
+    // The order numbers and dates of refunds in the ledger.
+    pub refunds: HashSet<(String, NaiveDate)>,
+    // The values of all the “import-key” metadata.
+    pub import_keys: HashSet<String>,
+    // All the accounts that are opened or posted to.
+    pub accounts: BTreeSet<String>,
+    // The currencies declared by commodity directives.
+    pub commodities: BTreeSet<String>,
+}
+
+// Whether `s` looks like an account name, like “Assets:Cash”.
+fn isAccount(s: &str) -> bool
+{
+    s.contains(':') && s.chars().next().is_some_and(|c| c.is_uppercase())
+}
+
+// The bits of a transaction that we care about.
+struct Entry
+{
+    date: NaiveDate,
+    order_number: Option<String>,
+    shipment_date: Option<NaiveDate>,
+    refund: bool,
+}
+
+impl Ledger
+{
+    fn add(&mut self, entry: Entry)
+    {
+        if let Some(order_number) = entry.order_number
+        {
+            if entry.refund
+            {
+                self.refunds.insert((order_number, entry.date));
+            }
+            else if let Some(date) = entry.shipment_date
+            {
+                self.shipments.insert((order_number, date));
+            }
+            else
+            {
+                self.order_numbers.insert(order_number);
+            }
+        }
+    }
+
+    // Scan a ledger file. This is not a real parser; it only looks at
+    // the lines that matter to us. Included files are not followed.
+    pub fn fromFile(filename: &Path) -> Result<Self, Error>
+    {
+        let contents = fs::read_to_string(filename).map_err(
+            |e| rterr!("Failed to read ledger {}: {}",
+                       filename.to_string_lossy(), e))?;
+        Ok(Self::fromText(&contents))
+    }
+
+    // Like fromFile(), but scan the text of a ledger.
+    pub fn fromText(contents: &str) -> Self
+    {
+        let mut ledger = Self {
+            order_numbers: HashSet::new(),
+            shipments: HashSet::new(),
+            refunds: HashSet::new(),
+            import_keys: HashSet::new(),
+            accounts: BTreeSet::new(),
+            commodities: BTreeSet::new(),
+        };
+        let mut entry: Option<Entry> = None;
+        for line in contents.lines()
+        {
+            // A directive starts with a date at the beginning of a line.
+            if let Some(date) = line.get(..10).and_then(
+                |s| NaiveDate::parse_from_str(&s.replace('/', "-"), "%Y-%m-%d")
+                    .ok())
+            {
+                if let Some(e) = entry.take()
+                {
+                    ledger.add(e);
+                }
+                let mut words = line[10..].split_whitespace();
+                match (words.next(), words.next())
+                {
+                    (Some("open"), Some(account)) => {
+                        ledger.accounts.insert(account.to_owned());
+                    },
+                    (Some("commodity"), Some(currency)) => {
+                        ledger.commodities.insert(currency.to_owned());
+                    },
+                    _ => {},
+                }
+                entry = Some(Entry { date, order_number: None, shipment_date: None,
+                                     refund: false });
+                continue;
+            }
+
+            // In ledger and hledger, “commodity USD” or “commodity
+            // 1,000.00 USD”.
+            if let Some(rest) = line.strip_prefix("commodity ")
+            {
+                if let Some(currency) = rest.split_whitespace().last()
+                {
+                    ledger.commodities.insert(currency.to_owned());
+                }
+                continue;
+            }
+
+            // Metadata in ledger and hledger are in comments.
+            let line = line.trim();
+            let line = line.strip_prefix(';').map(str::trim).unwrap_or(line);
+            if let Some(word) = line.split_whitespace().next()
+            {
+                if isAccount(word)
+                {
+                    ledger.accounts.insert(word.to_owned());
+                    continue;
+                }
+            }
+            if let Some(e) = entry.as_mut()
+            {
+                if let Some(value) = line.strip_prefix("order-number:")
+                {
+                    e.order_number = Some(value.trim().trim_matches('"')
+                                          .to_owned());
+                }
+                else if let Some(value) = line.strip_prefix("shipment-date:")
+                {
+                    e.shipment_date = NaiveDate::parse_from_str(
+                        value.trim(), "%Y-%m-%d").ok();
+                }
+                else if let Some(value) = line.strip_prefix("import-key:")
+                {
+                    ledger.import_keys.insert(value.trim().trim_matches('"')
+                                              .to_owned());
+                }
+                else if let Some(value) = line.strip_prefix("refund:")
+                {
+                    e.refund = value.trim() == "TRUE";
+                }
+            }
+        }
+        if let Some(e) = entry
+        {
+            ledger.add(e);
+        }
+        ledger
+    }
+
+    // Whether the order, or the shipment of the order if
+    // `shipment_date` is given, is in the ledger.
+    pub fn hasOrder(&self, order_number: &str,
+                    shipment_date: Option<NaiveDate>) -> bool
+    {
+        self.order_numbers.contains(order_number) || shipment_date.is_some_and(
+            |date| self.shipments.contains(&(order_number.to_owned(), date)))
+    }
+
+    pub fn hasRefund(&self, order_number: &str, date: NaiveDate) -> bool
+    {
+        self.refunds.contains(&(order_number.to_owned(), date))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn ymd(y: i32, m: u32, d: u32) -> NaiveDate
+    {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn testBeancount()
+    {
+        let ledger = Ledger::fromText(r#"
+2021-01-01 open Liabilities:Card USD
+2021-01-01 commodity USD
+
+2021-01-05 * "Amazon" "" ^111-0000001-0000001
+  order-number: "111-0000001-0000001"
+  import-key: "amazon:111-0000001-0000001"
+  Expenses:Misc 10.00 USD
+  Liabilities:Card -10.00 USD
+
+2021-01-08 * "Amazon" ""
+    order-number: 111-0000002-0000002
+    shipment-date: 2021-01-08
+  Expenses:Misc 5.00 USD
+  Liabilities:Card -5.00 USD
+
+2021-01-20 * "Amazon" "Refund"
+  order-number: "111-0000002-0000002"
+  refund: TRUE
+  Liabilities:Card 5.00 USD
+  Expenses:Misc -5.00 USD
+"#);
+        assert!(ledger.hasOrder("111-0000001-0000001", None));
+        assert!(ledger.hasOrder("111-0000001-0000001", Some(ymd(2021, 1, 6))));
+        // Only the one shipment of this order is in the ledger.
+        assert!(!ledger.hasOrder("111-0000002-0000002", None));
+        assert!(ledger.hasOrder("111-0000002-0000002", Some(ymd(2021, 1, 8))));
+        assert!(!ledger.hasOrder("111-0000002-0000002", Some(ymd(2021, 1, 9))));
+        assert!(ledger.hasRefund("111-0000002-0000002", ymd(2021, 1, 20)));
+        assert!(!ledger.hasRefund("111-0000001-0000001", ymd(2021, 1, 20)));
+        assert!(ledger.import_keys.contains("amazon:111-0000001-0000001"));
+        assert_eq!(ledger.accounts.iter().collect::<Vec<_>>(),
+                   vec!["Expenses:Misc", "Liabilities:Card"]);
+        assert_eq!(ledger.commodities.iter().collect::<Vec<_>>(), vec!["USD"]);
+    }
+
+    #[test]
+    fn testLedger()
+    {
+        let ledger = Ledger::fromText("\
+commodity 1,000.00 USD
+commodity EUR
+
+2021/01/05 * Amazon
+    ; order-number: 111-0000001-0000001
+    ; import-key: amazon:111-0000001-0000001
+    Expenses:Misc  10.00 USD
+    Liabilities:Card  -10.00 USD
+
+2021/01/20 * Amazon  ; Refund
+    ; order-number: 111-0000001-0000001
+    ; refund: TRUE
+    Liabilities:Card  10.00 USD
+    Expenses:Misc  -10.00 USD
+");
+        assert!(ledger.hasOrder("111-0000001-0000001", None));
+        assert!(ledger.hasRefund("111-0000001-0000001", ymd(2021, 1, 20)));
+        assert!(ledger.import_keys.contains("amazon:111-0000001-0000001"));
+        assert!(!ledger.hasOrder("111-0000002-0000002", None));
+        assert_eq!(ledger.commodities.iter().collect::<Vec<_>>(), vec!["EUR", "USD"]);
+        assert!(!ledger.accounts.contains("commodity"));
+    }
+}
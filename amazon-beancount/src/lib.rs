@@ -0,0 +1,20 @@
+#![allow(non_snake_case)]
+
+#[macro_use]
+pub mod error;
+pub mod order_record;
+pub mod config;
+pub mod columns;
+pub mod rules;
+pub mod ledger;
+pub mod date;
+pub mod marketplace;
+pub mod transaction;
+pub mod refund;
+pub mod payment;
+pub mod grouping;
+pub mod reconcile;
+pub mod review;
+pub mod render;
+pub mod importer;
+pub mod summary;
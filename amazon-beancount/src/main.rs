@@ -1,28 +1,311 @@
 #![allow(non_snake_case)]
 use std::io::prelude::*;
-
-use clap;
+use std::fs;
+use std::path::Path;
+use std::collections::BTreeSet;
 
 #[macro_use]
-mod error;
-mod order_record;
-mod config;
+extern crate amazon_beancount;
+
+use amazon_beancount::{config, grouping, importer, ledger, reconcile, refund,
+                       render, review, summary, transaction};
+use amazon_beancount::error::Error;
+use amazon_beancount::marketplace::{self, Marketplace};
+use amazon_beancount::order_record::Order;
+use refund::Refund;
+use transaction::Transaction;
+use reconcile::Charge;
+use render::Renderer;
+
+// Append the transactions to the Beancount file `target`, or print
+// them to stdout if `target` is None.
+fn appendTransactions(transactions: &[Transaction], renderer: &dyn Renderer,
+                      target: Option<&String>) -> Result<(), Error>
+{
+    let mut content = String::new();
+    for t in transactions
+    {
+        content.push('\n');
+        content.push_str(&renderer.entry(t)?);
+        content.push('\n');
+    }
+
+    if let Some(filename) = target
+    {
+        let mut file = fs::OpenOptions::new().create(true).append(true)
+            .open(filename).map_err(
+                |e| rterr!("Failed to open {}: {}", filename, e))?;
+        file.write_all(content.as_bytes()).map_err(
+            |e| rterr!("Failed to write {}: {}", filename, e))?;
+        println!("Appended {} entries to {}.", transactions.len(), filename);
+        Ok(())
+    }
+    else
+    {
+        println!("Accepted entries:\n{}", content);
+        Ok(())
+    }
+}
+
+// Account names for completion in the review.
+fn knownAccounts(conf: &config::Config, ledgers: &[ledger::Ledger]) -> Vec<String>
+{
+    let mut accounts: BTreeSet<String> = BTreeSet::new();
+    for ledger in ledgers
+    {
+        accounts.extend(ledger.accounts.iter().cloned());
+    }
+    accounts.extend([&conf.account_credit, &conf.account_tax,
+                     &conf.account_shipping, &conf.account_expense,
+                     &conf.account_gift_card, &conf.account_promotion]
+                    .iter().map(|a| a.to_string()));
+    accounts.extend(conf.rules.iter().map(|r| r.account.clone()));
+    accounts.extend(conf.payment.iter().map(|p| p.account.clone()));
+    accounts.into_iter().collect()
+}
+
+// The header of a generated file, without the commodities declared in
+// `ledger`, or nothing with --no-header.
+fn header(opts: &clap::ArgMatches, transactions: &[Transaction], renderer: &dyn Renderer,
+          ledger: Option<&ledger::Ledger>) -> String
+{
+    if opts.get_flag("no-header")
+    {
+        return String::new();
+    }
+    let declared = ledger.map(|l| l.commodities.clone()).unwrap_or_default();
+    renderer.header(transactions, &declared) + "\n"
+}
+
+// Write all transactions as a complete file to `output`, or to stdout
+// if `output` is None.
+fn exportTransactions(transactions: &[Transaction], renderer: &dyn Renderer,
+                      header: String, output: Option<&String>) -> Result<(), Error>
+{
+    let mut content = header;
+    for t in transactions
+    {
+        content.push('\n');
+        content.push_str(&renderer.entry(t)?);
+        content.push('\n');
+    }
+    writeOutput(&content, output)
+}
+
+// Write `content` to `output`, or to stdout if `output` is None.
+fn writeOutput(content: &str, output: Option<&String>) -> Result<(), Error>
+{
+    if let Some(filename) = output
+    {
+        fs::write(filename, content).map_err(
+            |e| rterr!("Failed to write {}: {}", filename, e))
+    }
+    else
+    {
+        std::io::stdout().write_all(content.as_bytes()).map_err(
+            |e| rterr!("Failed to write to stdout: {}", e))
+    }
+}
+
+// Read the orders in an orders or items report. Multiple items in the
+// same order are sperated in the CSV. Combine them.
+fn readOrders(filename: &str, conf: &config::Config) -> Result<Vec<Order>, Error>
+{
+    let file = fs::File::open(filename).map_err(
+        |_| rterr!("Failed to read CSV file: {}", filename))?;
+    let (orders, conflicts) = grouping::readOrders(file, conf).map_err(|e| match e
+    {
+        Error::RuntimeError(msg) => rterr!("{}: {}", filename, msg),
+        e => e,
+    })?;
+    for conflict in conflicts
+    {
+        eprintln!("Warning: rows of order {} have different dates: {}.",
+                  conflict.order_number,
+                  conflict.dates.iter().map(|d| d.format("%F").to_string())
+                  .collect::<Vec<_>>().join(", "));
+    }
+    for warning in orders.iter().filter_map(|order| order.giftCardWarning(conf))
+    {
+        eprintln!("Warning: {}", warning);
+    }
+    Ok(orders)
+}
+
+// Review the orders one by one, or write them all at once in batch
+// mode.
+fn review(opts: &clap::ArgMatches, conf: &config::Config) -> Result<(), Error>
+{
+    let mut orders = readOrders(opts.get_one::<String>("input").unwrap(), conf)?;
+    let mut refunds = match opts.get_one::<String>("refunds")
+    {
+        Some(filename) => Refund::fromCSV(filename, conf)?,
+        None => Vec::new(),
+    };
+    for refund in refunds.iter_mut()
+    {
+        refund.matchOrder(&orders);
+    }
+
+    let mut start_from: usize = 0;
+    if let Some(after) = opts.get_one::<String>("after")
+    {
+        if let Some(i) = orders.iter().rposition(
+            |order| &order.order_number == after)
+        {
+            start_from = i + 1;
+        }
+    }
+
+    let mut orders: Vec<Order> = orders.drain(start_from..).collect();
+    let mut ledgers = Vec::new();
+    if let Some(filename) = opts.get_one::<String>("ledger")
+    {
+        let ledger = ledger::Ledger::fromFile(Path::new(filename))?;
+        let count = orders.len();
+        orders.retain(
+            |order| !ledger.hasOrder(&order.order_number, order.shipment_date));
+        eprintln!("Skipped {} orders that are already in {}.",
+                  count - orders.len(), filename);
+        let count = refunds.len();
+        refunds.retain(|r| !ledger.hasRefund(&r.order_number, r.date));
+        if count > 0
+        {
+            eprintln!("Skipped {} refunds that are already in {}.",
+                      count - refunds.len(), filename);
+        }
+        ledgers.push(ledger);
+    }
+
+    let transactions: Vec<Transaction> =
+        orders.iter().map(|order| order.transaction(conf))
+        .chain(refunds.iter().map(|r| r.transaction(conf)))
+        .collect();
+    let renderer = render::renderer(opts.get_one::<String>("format").unwrap())?;
+    if opts.get_flag("batch")
+    {
+        let header = header(opts, &transactions, renderer.as_ref(), ledgers.first());
+        return exportTransactions(&transactions, renderer.as_ref(), header,
+                                  opts.get_one::<String>("output"));
+    }
+
+    let target = opts.get_one::<String>("append");
+    if let Some(filename) = target
+    {
+        if Path::new(filename).exists()
+        {
+            ledgers.push(ledger::Ledger::fromFile(Path::new(filename))?);
+        }
+    }
+    let mut reviewer = review::Reviewer::new(knownAccounts(conf, &ledgers))?;
+    let mut accepted = Vec::new();
+    for mut t in transactions
+    {
+        match reviewer.review(&mut t, renderer.as_ref())?
+        {
+            review::Decision::Accept => accepted.push(t),
+            review::Decision::Skip => {},
+            review::Decision::Quit => break,
+        }
+    }
+    appendTransactions(&accepted, renderer.as_ref(), target)?;
+    println!("All done.");
+    Ok(())
+}
+
+// Match the orders against the charges in a bank statement, and print
+// the result.
+fn reconcileWithBank(opts: &clap::ArgMatches, conf: &config::Config)
+                     -> Result<(), Error>
+{
+    let orders = readOrders(opts.get_one::<String>("input").unwrap(), conf)?;
+    let charges = Charge::fromCSV(opts.get_one::<String>("bank").unwrap(), conf)?;
+    let window = *opts.get_one::<i64>("window").unwrap();
+    let result = reconcile::reconcile(&orders, &charges, window);
+
+    let currency = conf.marketplace.currency();
+    let places = conf.marketplace.decimalPlaces();
+    let orderLine = |order: &Order| format!(
+        "{} {} {:.*} {}", order.chargeDate().format("%F"), order.order_number,
+        places, order.charged(), currency);
+    let chargeLine = |charge: &Charge| format!(
+        "{} {:.*} {} {}", charge.date.format("%F"), places, charge.amount,
+        currency, charge.description);
 
-use error::Error;
-use order_record::Order;
+    println!("Matched ({}):", result.matched.len());
+    for (i, j) in &result.matched
+    {
+        println!("  {}  <->  {}", orderLine(&orders[*i]), chargeLine(&charges[*j]));
+    }
+    println!("\nUnmatched orders ({}):", result.unmatched_orders.len());
+    for i in &result.unmatched_orders
+    {
+        println!("  {}", orderLine(&orders[*i]));
+    }
+    println!("\nUnmatched charges ({}):", result.unmatched_charges.len());
+    for j in &result.unmatched_charges
+    {
+        println!("  {}", chargeLine(&charges[*j]));
+    }
+    Ok(())
+}
 
-fn pressAnyKeyToContinue()
+// Print the sums of the orders by month, year or expense account.
+fn summarize(opts: &clap::ArgMatches, conf: &config::Config) -> Result<(), Error>
 {
-    println!("Press any key to continue...");
-    std::io::stdin().read(&mut [0u8]).unwrap();
+    let orders = readOrders(opts.get_one::<String>("input").unwrap(), conf)?;
+    let grouping = summary::Grouping::fromName(
+        opts.get_one::<String>("by").unwrap())?;
+    let rows = summary::summarize(&orders, conf, grouping);
+    let places = conf.marketplace.decimalPlaces();
+    let content = if opts.get_flag("csv")
+    {
+        summary::toCSV(&rows, grouping, places)?
+    }
+    else
+    {
+        summary::table(&rows, grouping, places)
+    };
+    writeOutput(&content, None)
 }
 
-fn printOrder(order: &Order, conf: &config::Config)
+// Extract the transactions from all the reports in a directory, like
+// bean-extract. The transactions of each file are preceded by a
+// “**** path” line.
+fn extractReports(opts: &clap::ArgMatches, conf: &config::Config)
+                  -> Result<(), Error>
 {
-    println!("Next order:\n");
-    println!("{}", order.beancountEntry(conf));
-    println!("\nURL: {}\n", order.url());
-    pressAnyKeyToContinue();
+    let ledger = match opts.get_one::<String>("ledger")
+    {
+        Some(filename) => Some(ledger::Ledger::fromFile(Path::new(filename))?),
+        None => None,
+    };
+    let dir = Path::new(opts.get_one::<String>("dir").unwrap());
+    let extracted = importer::extractDir(
+        dir, conf, ledger.as_ref(),
+        |path, reason| eprintln!("Skipping {}: {}.", path.to_string_lossy(), reason))?;
+
+    let renderer = render::renderer(opts.get_one::<String>("format").unwrap())?;
+    let all: Vec<Transaction> = extracted.iter()
+        .flat_map(|e| e.transactions.iter().cloned()).collect();
+    let mut content = header(opts, &all, renderer.as_ref(), ledger.as_ref());
+    for e in &extracted
+    {
+        eprintln!("Extracted {} transactions from {} ({}).",
+                  e.transactions.len(), e.path.to_string_lossy(), e.kind.name());
+        for warning in &e.warnings
+        {
+            eprintln!("Warning: {}", warning);
+        }
+        content.push_str(&format!("\n**** {}\n", e.path.to_string_lossy()));
+        for t in &e.transactions
+        {
+            content.push('\n');
+            content.push_str(&renderer.entry(t)?);
+            content.push('\n');
+        }
+    }
+    writeOutput(&content, opts.get_one::<String>("output"))
 }
 
 fn main() -> Result<(), Error>
@@ -30,53 +313,148 @@ fn main() -> Result<(), Error>
     let opts = clap::Command::new("Amazon order history to beancount inspector")
         .version("0.0.1")
         .author("MetroWind <chris.corsair@gmail.com>")
+        .args_conflicts_with_subcommands(true)
         .arg(clap::Arg::new("after")
              .short('a')
              .long("after")
              .value_name("ORDER")
              .help("Only find orders placed after ORDER."))
+        .arg(clap::Arg::new("batch")
+             .short('b')
+             .long("batch")
+             .action(clap::ArgAction::SetTrue)
+             .help("Write all orders at once without interaction."))
+        .arg(clap::Arg::new("output")
+             .short('o')
+             .long("output")
+             .value_name("FILE")
+             .requires("batch")
+             .help("In batch mode, write the orders to FILE instead of stdout."))
+        .arg(clap::Arg::new("format")
+             .short('f')
+             .long("format")
+             .value_name("FORMAT")
+             .value_parser(render::FORMATS.to_vec())
+             .default_value("beancount")
+             .help("The output format."))
+        .arg(clap::Arg::new("no-header")
+             .long("no-header")
+             .action(clap::ArgAction::SetTrue)
+             .requires("batch")
+             .help("In batch mode, do not declare the commodities at the beginning of the output."))
+        .arg(clap::Arg::new("append")
+             .long("append")
+             .value_name("FILE")
+             .conflicts_with("batch")
+             .help("Append the accepted orders to the Beancount file FILE after review."))
+        .arg(clap::Arg::new("ledger")
+             .short('l')
+             .long("ledger")
+             .value_name("FILE")
+             .help("Skip orders and refunds that are already in the Beancount file FILE."))
+        .arg(clap::Arg::new("refunds")
+             .short('r')
+             .long("refunds")
+             .value_name("FILE")
+             .help("Also read refunds from the refunds report FILE."))
+        .arg(clap::Arg::new("config")
+             .short('c')
+             .long("config")
+             .value_name("FILE")
+             .global(true)
+             .help("Read config from FILE. Default: $XDG_CONFIG_HOME/amazon-beancount.toml"))
+        .arg(clap::Arg::new("marketplace")
+             .short('m')
+             .long("marketplace")
+             .value_name("SITE")
+             .value_parser(marketplace::NAMES.to_vec())
+             .global(true)
+             .help("The Amazon site of the orders, instead of the one in the config."))
         .arg(clap::Arg::new("input")
              .help("The order record CSV file. You can acquire this file from https://www.amazon.com/gp/b2b/reports.")
              .required(true)
              .value_name("FILE")
              .index(1))
+        .subcommand(
+            clap::Command::new("reconcile")
+                .about("Match orders against the charges in a bank transaction CSV.")
+                .arg(clap::Arg::new("window")
+                     .short('w')
+                     .long("window")
+                     .value_name("DAYS")
+                     .value_parser(clap::value_parser!(i64))
+                     .default_value("7")
+                     .help("Maximal number of days between an order and its charge."))
+                .arg(clap::Arg::new("bank")
+                     .help("The transaction CSV file from the bank.")
+                     .required(true)
+                     .value_name("BANK")
+                     .index(1))
+                .arg(clap::Arg::new("input")
+                     .help("The order record CSV file.")
+                     .required(true)
+                     .value_name("FILE")
+                     .index(2)))
+        .subcommand(
+            clap::Command::new("summary")
+                .about("Show the sums of the orders by month, year or expense account.")
+                .arg(clap::Arg::new("by")
+                     .long("by")
+                     .value_name("GROUPING")
+                     .value_parser(summary::GROUPINGS.to_vec())
+                     .default_value("month")
+                     .help("How to group the orders."))
+                .arg(clap::Arg::new("csv")
+                     .long("csv")
+                     .action(clap::ArgAction::SetTrue)
+                     .help("Write CSV instead of a table."))
+                .arg(clap::Arg::new("input")
+                     .help("The order record CSV file.")
+                     .required(true)
+                     .value_name("FILE")
+                     .index(1)))
+        .subcommand(
+            clap::Command::new("extract")
+                .about("Extract transactions from all the reports in a directory, like bean-extract.")
+                .arg(clap::Arg::new("output")
+                     .short('o')
+                     .long("output")
+                     .value_name("FILE")
+                     .help("Write the transactions to FILE instead of stdout."))
+                .arg(clap::Arg::new("format")
+                     .short('f')
+                     .long("format")
+                     .value_name("FORMAT")
+                     .value_parser(render::FORMATS.to_vec())
+                     .default_value("beancount")
+                     .help("The output format."))
+                .arg(clap::Arg::new("ledger")
+                     .short('l')
+                     .long("ledger")
+                     .value_name("FILE")
+                     .help("Skip transactions that are already in the ledger FILE."))
+                .arg(clap::Arg::new("no-header")
+                     .long("no-header")
+                     .action(clap::ArgAction::SetTrue)
+                     .help("Do not declare the commodities at the beginning of the output."))
+                .arg(clap::Arg::new("dir")
+                     .help("The directory of downloaded reports.")
+                     .required(true)
+                     .value_name("DIR")
+                     .index(1)))
         .get_matches();
 
-    let conf = config::Config::default();
-    let mut orders = Order::fromCSV(opts.get_one::<String>("input").unwrap())?;
-    let mut start_from: usize = 0;
-    if let Some(after) = opts.get_one::<String>("after")
+    let mut conf = config::Config::load(
+        opts.get_one::<String>("config").map(Path::new))?;
+    if let Some(name) = opts.get_one::<String>("marketplace")
     {
-        for i in 0..orders.len()
-        {
-            if &orders[i].order_number == after
-            {
-                start_from = i + 1;
-                break;
-            }
-        }
+        conf.marketplace = Marketplace::fromName(name)?;
     }
-
-    // Multiple items in the same order are sperated in the CSV.
-    // Combine them and print. This assumes that items in the same
-    // order are consequtive in the CSV.
-    let mut last_order = Order::new();
-    for order in orders.drain(start_from..)
+    match opts.subcommand()
     {
-        if order.order_number == last_order.order_number
-        {
-            last_order += order.clone();
-        }
-        else
-        {
-            if !last_order.order_number.is_empty()
-            {
-                printOrder(&last_order, &conf);
-            }
-            last_order = order;
-        }
+        Some(("reconcile", sub_opts)) => reconcileWithBank(sub_opts, &conf),
+        Some(("extract", sub_opts)) => extractReports(sub_opts, &conf),
+        Some(("summary", sub_opts)) => summarize(sub_opts, &conf),
+        _ => review(&opts, &conf),
     }
-    printOrder(&last_order, &conf);
-    println!("All done.");
-    Ok(())
 }
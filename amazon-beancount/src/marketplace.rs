@@ -0,0 +1,202 @@
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::error::Error;
+
+// The names of the marketplaces, as in the config file and on the
+// command line.
+pub const NAMES: &[&str] = &["amazon.com", "amazon.ca", "amazon.co.uk",
+                             "amazon.de", "amazon.fr", "amazon.co.jp"];
+
+// An Amazon site. This decides the currency, the price format, and
+// the URL of order pages.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum Marketplace
+{
+    #[default]
+    #[serde(rename = "amazon.com")]
+    US,
+    #[serde(rename = "amazon.ca")]
+    Canada,
+    #[serde(rename = "amazon.co.uk")]
+    UK,
+    #[serde(rename = "amazon.de")]
+    Germany,
+    #[serde(rename = "amazon.fr")]
+    France,
+    #[serde(rename = "amazon.co.jp")]
+    Japan,
+}
+
+impl Marketplace
+{
+    pub fn fromName(name: &str) -> Result<Self, Error>
+    {
+        match name
+        {
+            "amazon.com" => Ok(Marketplace::US),
+            "amazon.ca" => Ok(Marketplace::Canada),
+            "amazon.co.uk" => Ok(Marketplace::UK),
+            "amazon.de" => Ok(Marketplace::Germany),
+            "amazon.fr" => Ok(Marketplace::France),
+            "amazon.co.jp" => Ok(Marketplace::Japan),
+            _ => Err(rterr!("Unknown marketplace: {}", name)),
+        }
+    }
+
+    pub fn domain(&self) -> &'static str
+    {
+        match self
+        {
+            Marketplace::US => "www.amazon.com",
+            Marketplace::Canada => "www.amazon.ca",
+            Marketplace::UK => "www.amazon.co.uk",
+            Marketplace::Germany => "www.amazon.de",
+            Marketplace::France => "www.amazon.fr",
+            Marketplace::Japan => "www.amazon.co.jp",
+        }
+    }
+
+    pub fn currency(&self) -> &'static str
+    {
+        match self
+        {
+            Marketplace::US => "USD",
+            Marketplace::Canada => "CAD",
+            Marketplace::UK => "GBP",
+            Marketplace::Germany | Marketplace::France => "EUR",
+            Marketplace::Japan => "JPY",
+        }
+    }
+
+    // Number of digits after the decimal point in amounts.
+    pub fn decimalPlaces(&self) -> usize
+    {
+        match self
+        {
+            Marketplace::Japan => 0,
+            _ => 2,
+        }
+    }
+
+    fn decimalSeparator(&self) -> char
+    {
+        match self
+        {
+            Marketplace::Germany | Marketplace::France => ',',
+            _ => '.',
+        }
+    }
+
+    // Parse a price like “$1,234.56”, “€1.234,56” or “￥1,234” into
+    // a decimal. The number may have a leading minus sign, thousands
+    // separators (or spaces) between groups of three digits, and at
+    // most one decimal separator. Currency symbols and codes may only
+    // come before or after the number. Anything else is an error.
+    pub fn parsePrice(&self, price: &str) -> Result<Decimal, Error>
+    {
+        let error = || rterr!("Failed to parse '{}' into decimal", price);
+        let decimal_sep = self.decimalSeparator();
+        let group_sep = if decimal_sep == '.' { ',' } else { '.' };
+        let isCurrency = |c: char| c.is_ascii_uppercase() || "$€£¥￥".contains(c);
+
+        let mut number = price.trim();
+        let mut negative = false;
+        if let Some(rest) = number.strip_prefix('-')
+        {
+            negative = true;
+            number = rest;
+        }
+        number = number.trim_start_matches(isCurrency).trim_end_matches(isCurrency)
+            .trim();
+        if let Some(rest) = number.strip_prefix('-')
+        {
+            if negative
+            {
+                return Err(error());
+            }
+            negative = true;
+            number = rest;
+        }
+
+        let (integer, fraction) = match number.split_once(decimal_sep)
+        {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (number, None),
+        };
+        let isDigits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+        let groups: Vec<&str> = integer
+            .split(|c: char| c == group_sep || c.is_whitespace()).collect();
+        let grouped = groups.len() == 1 || (
+            groups[0].len() <= 3 && groups[1..].iter().all(|g| g.len() == 3));
+        if !grouped || !groups.iter().all(|g| isDigits(g))
+            || fraction.is_some_and(|f| !isDigits(f))
+        {
+            return Err(error());
+        }
+
+        let mut result = String::new();
+        if negative
+        {
+            result.push('-');
+        }
+        result.push_str(&groups.concat());
+        if let Some(fraction) = fraction
+        {
+            result.push('.');
+            result.push_str(fraction);
+        }
+        result.parse().map_err(|_| error())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn dec(s: &str) -> Decimal
+    {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn testParsePrice()
+    {
+        assert_eq!(Marketplace::US.parsePrice("$1,234.56").unwrap(), dec("1234.56"));
+        assert_eq!(Marketplace::US.parsePrice("-$5.00").unwrap(), dec("-5"));
+        assert_eq!(Marketplace::Canada.parsePrice("CDN$ 1,234.56").unwrap(),
+                   dec("1234.56"));
+        assert_eq!(Marketplace::Germany.parsePrice("€1.234,56").unwrap(),
+                   dec("1234.56"));
+        assert_eq!(Marketplace::France.parsePrice("12,30 EUR").unwrap(),
+                   dec("12.3"));
+        assert_eq!(Marketplace::France.parsePrice("1\u{202f}234,56 €").unwrap(),
+                   dec("1234.56"));
+        assert_eq!(Marketplace::Japan.parsePrice("￥1,234").unwrap(), dec("1234"));
+        assert!(Marketplace::US.parsePrice("$1.2.3").is_err());
+        assert!(Marketplace::US.parsePrice("").is_err());
+        assert!(Marketplace::US.parsePrice("$").is_err());
+        assert!(Marketplace::US.parsePrice("N/A").is_err());
+        assert!(Marketplace::US.parsePrice("12 items").is_err());
+        assert!(Marketplace::Germany.parsePrice("$12.99").is_err());
+        assert!(Marketplace::US.parsePrice("1,23").is_err());
+        assert!(Marketplace::US.parsePrice("1E5").is_err());
+        assert!(Marketplace::US.parsePrice("$1,2,3.4").is_err());
+        assert!(Marketplace::US.parsePrice("1.2,3").is_err());
+        assert!(Marketplace::US.parsePrice("--5").is_err());
+        assert_eq!(Marketplace::US.parsePrice(" $-0.50 ").unwrap(), dec("-0.5"));
+        assert_eq!(Marketplace::US.parsePrice("12").unwrap(), dec("12"));
+    }
+
+    #[test]
+    fn testFromName()
+    {
+        for name in NAMES
+        {
+            assert!(Marketplace::fromName(name).is_ok());
+        }
+        assert_eq!(Marketplace::fromName("amazon.de").unwrap(), Marketplace::Germany);
+        assert!(Marketplace::fromName("amazon.es").is_err());
+    }
+}
@@ -1,146 +1,350 @@
 use chrono::prelude::*;
-use csv;
+use rust_decimal::Decimal;
 
 use crate::error::Error;
 use crate::config::Config;
+use crate::columns::{Column, ColumnMap};
+use crate::marketplace::Marketplace;
+use crate::rules;
+use crate::payment::{self, Instrument};
+use crate::date::DateParser;
+use crate::transaction::{self, MetaValue, Posting, Transaction};
 
-// Take a date string like “01/31/21” and return its interpreted local
-// date (“2021-01-31”).
-fn dateFromDumbSlash(date: &str) -> Result<Date<Local>, Error>
+// The kinds of report that Amazon provides.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Report
 {
-    let parts: Vec<&str> = date.split('/').collect();
-    Ok(Local.ymd((String::from("20") + parts[2]).parse().map_err(
-        |_| rterr!("Failed to parse date"))?,
-                 parts[0].parse().map_err(
-                     |_| rterr!("Failed to parse date"))?,
-                 parts[1].parse().map_err(
-                     |_| rterr!("Failed to parse date"))?))
+    // One row per shipment, with shipping, tax and total.
+    Orders,
+    // One row per item, with title, category and item total.
+    Items,
 }
 
-// Strip a leading ‘$’ if present.
-fn stripDollar(price: &str) -> String
+impl Report
 {
-    if let Some(s) = price.get(0..1)
+    pub fn detect(columns: &ColumnMap) -> Self
     {
-        if s == "$"
+        if columns.has(Column::ItemTotal)
         {
-            if let Some(num_part) = price.get(1..)
-            {
-                num_part.to_owned()
-            }
-            else
-            {
-                String::new()
-            }
+            Report::Items
         }
         else
         {
-            price.to_owned()
+            Report::Orders
         }
     }
-    else
+
+    pub fn requiredColumns(&self) -> &'static [Column]
     {
-        String::new()
+        match self
+        {
+            Report::Orders => &[
+                Column::OrderDate, Column::OrderNumber, Column::Shipping,
+                Column::Tax, Column::Total],
+            Report::Items => &[
+                Column::OrderDate, Column::OrderNumber, Column::Title,
+                Column::Category, Column::Quantity, Column::ItemSubtotal,
+                Column::ItemTax, Column::ItemTotal],
+        }
     }
 }
 
-// Convert “$1.23” to float 1.23. Return 0 if string is empty.
-fn priceStr2Float(price: &str) -> Result<f64, Error>
+// A line item in an order. Only the items report has these.
+#[derive(Clone)]
+pub struct Item
 {
-    let p = stripDollar(price);
-    if p.is_empty()
-    {
-        Ok(0.0)
-    }
-    else
-    {
-        p.parse().map_err(|_| rterr!("Failed to parse '{}' into float", price))
-    }
+    pub title: String,
+    pub category: String,
+    pub quantity: u32,
+    pub sub_total: Decimal,
 }
 
 #[derive(Clone)]
 pub struct Order
 {
-    pub date: Date<Local>,
+    pub date: NaiveDate,
     pub order_number: String,
-    tax: f64,
-    shipping: f64,
-    sub_total: f64,
+    // If the report has shipment dates, an order is split into one
+    // Order per shipment, each charged separately.
+    pub shipment_date: Option<NaiveDate>,
+    // The line in the report of the first row of this order.
+    pub line: u64,
+    tax: Decimal,
+    shipping: Decimal,
+    sub_total: Decimal,
+    items: Vec<Item>,
+    // Discount from promotions, which is already taken off sub_total.
+    promotion: Decimal,
+    // The part of sub_total paid by gift card, if the report says so.
+    gift_card: Option<Decimal>,
+    // The payment instruments, if the report has them.
+    instruments: Vec<Instrument>,
+    pub marketplace: Marketplace,
 }
 
 impl Order
 {
-    pub fn new() -> Self
+    fn fromCSVRow(row: &csv::StringRecord, line: u64, date: NaiveDate,
+                  shipment_date: Option<NaiveDate>, columns: &ColumnMap,
+                  report: Report, marketplace: Marketplace)
+                  -> Result<Self, Error>
     {
-        Self {
-            date: Local::today(),
-            order_number: String::new(),
-            tax: 0.0,
-            shipping: 0.0,
-            sub_total: 0.0,
+        let get = |column: Column| columns.get(row, column).ok_or_else(
+            || rterr!("Missing column {}", column.name()));
+        let price = |column: Column| marketplace.parsePrice(get(column)?).map_err(
+            |e| rterr!("Failed to parse {}: {}", column.name(), e));
+
+        // Optional columns. An empty cell is the same as no column.
+        let opt_price = |column: Column| match columns.get(row, column)
+        {
+            Some(s) if !s.trim().is_empty() => marketplace.parsePrice(s).map(Some).map_err(
+                |e| rterr!("Failed to parse {}: {}", column.name(), e)),
+            _ => Ok(None),
+        };
+
+        let order_number = get(Column::OrderNumber)?.to_owned();
+        let instruments = payment::parseInstruments(
+            columns.get(row, Column::Payment).unwrap_or(""));
+        let promotion = opt_price(Column::Promotion)?.unwrap_or(Decimal::ZERO);
+        let gift_card = opt_price(Column::GiftCard)?;
+        match report
+        {
+            Report::Orders => Ok(Self {
+                date, order_number, shipment_date, line,
+                tax: price(Column::Tax)?,
+                shipping: price(Column::Shipping)?,
+                sub_total: price(Column::Total)?,
+                items: Vec::new(),
+                promotion, gift_card,
+                instruments,
+                marketplace,
+            }),
+            Report::Items => {
+                let quantity = get(Column::Quantity)?;
+                let item = Item {
+                    title: get(Column::Title)?.to_owned(),
+                    category: get(Column::Category)?.to_owned(),
+                    quantity: quantity.trim().parse().map_err(
+                        |_| rterr!("Failed to parse quantity '{}'", quantity))?,
+                    sub_total: price(Column::ItemSubtotal)?,
+                };
+                Ok(Self {
+                    date, order_number, shipment_date, line,
+                    tax: price(Column::ItemTax)?,
+                    shipping: Decimal::ZERO,
+                    sub_total: price(Column::ItemTotal)?,
+                    items: vec![item],
+                    promotion, gift_card,
+                    instruments,
+                    marketplace,
+                })
+            },
         }
     }
 
-    fn fromCSVRow(row: csv::StringRecord) -> Result<Self, Error>
+    // Read an orders or items report from a file, and call `f` with
+    // each row.
+    pub fn readCSV<F>(filename: &str, config: &Config, f: F)
+                      -> Result<(), Error>
+        where F: FnMut(Self)
     {
-        let err = rterr!("Invalid CSV");
-
-        Ok(Self {
-            date: dateFromDumbSlash(row.get(0).ok_or_else(|| err.clone())?)
-                .map_err(|e| rterr!("Failed to parse date (column 0): {}", e))?,
-            order_number: row.get(1).ok_or_else(|| err.clone())?.to_owned(),
-            tax: priceStr2Float(row.get(19).ok_or_else(|| err.clone())?)
-                .map_err(|e| rterr!("Failed to parse tax (column 19): {}", e))?,
-            shipping: priceStr2Float(row.get(16).ok_or_else(|| err.clone())?)
-                .map_err(|e| rterr!("Failed to parse shipping (column 16): {}",
-                                    e))?,
-            sub_total: priceStr2Float(row.get(20).ok_or_else(|| err.clone())?)
-                .map_err(
-                    |e| rterr!("Failed to parse total (column 20): {}", e))?,
+        let file = std::fs::File::open(filename).map_err(
+            |_| rterr!("Failed to read CSV file: {}", filename))?;
+        Self::readCSVFrom(file, config, f).map_err(|e| match e
+        {
+            Error::RuntimeError(msg) => rterr!("{}: {}", filename, msg),
+            e => e,
         })
     }
 
-    pub fn fromCSV(filename: &str) -> Result<Vec<Self>, Error>
+    // Like readCSV(), but read the report from `reader`. The rows of
+    // an order are not combined; use grouping::readOrders() for that.
+    pub fn readCSVFrom<R, F>(reader: R, config: &Config, mut f: F)
+                             -> Result<(), Error>
+        where R: std::io::Read, F: FnMut(Self)
     {
         // This skips the first line by default.
-        let mut csv_reader = csv::Reader::from_path(filename).map_err(
-            |_| rterr!("Failed to read CSV file: {}", filename))?;
-        csv_reader.records().map(|row_maybe| {
+        let mut csv_reader = csv::Reader::from_reader(reader);
+        let header = csv_reader.headers().map_err(
+            |_| rterr!("Failed to read CSV header"))?.clone();
+        let columns = ColumnMap::fromHeader(&header, &config.columns);
+        let report = Report::detect(&columns);
+        columns.require(report.requiredColumns())?;
+
+        let mut dates = DateParser::new();
+        let mut shipment_dates = DateParser::new();
+        for row_maybe in csv_reader.records()
+        {
             let row = row_maybe.map_err(
                 |_| rterr!("Failed to read a CSV row."))?;
-            Self::fromCSVRow(row.clone()).map_err(|e| rterr!("{}: {:?}", e, row))
-        }).collect()
+            let line = row.position().map(|p| p.line()).unwrap_or(0);
+            let date = dates.parse(
+                columns.get(&row, Column::OrderDate).unwrap_or(""), line)?;
+            let shipment_date = shipment_dates.parseOptional(
+                columns.get(&row, Column::ShipmentDate).unwrap_or(""), line)?;
+            f(Self::fromCSVRow(&row, line, date, shipment_date, &columns,
+                               report, config.marketplace)
+              .map_err(|e| rterr!("{}: {:?}", e, row))?);
+        }
+        Ok(())
+    }
+
+    pub fn tax(&self) -> Decimal
+    {
+        self.tax
+    }
+
+    pub fn shipping(&self) -> Decimal
+    {
+        self.shipping
+    }
+
+    // The total of the order, including tax and shipping, after
+    // promotions.
+    pub fn total(&self) -> Decimal
+    {
+        self.sub_total
+    }
+
+    // The items of the order. Empty unless read from an items report.
+    pub fn items(&self) -> &[Item]
+    {
+        &self.items
     }
 
     pub fn url(&self) -> String
     {
-        format!("https://www.amazon.com/gp/your-account/order-details?orderID={}",
-                self.order_number)
+        format!("https://{}/gp/your-account/order-details?orderID={}",
+                self.marketplace.domain(), self.order_number)
+    }
+
+    // The postings of the transaction of this order. If the order has
+    // items, each item is a posting to the account given by the rules;
+    // otherwise the expense is whatever is left after shipping and
+    // tax, before promotions. Promotions are income, and the part paid
+    // by gift card comes out of the gift card account. Shipping and tax
+    // are either posted separately or distributed into the expense,
+    // by the config. Zero amounts other than the expense are omitted.
+    pub fn postings(&self, config: &Config) -> Vec<Posting>
+    {
+        let mut postings = Vec::new();
+        if self.items.is_empty()
+        {
+            postings.push(Posting::newExpense(
+                &config.account_expense,
+                self.sub_total - self.shipping - self.tax + self.promotion));
+        }
+        for item in &self.items
+        {
+            let account = rules::findAccount(
+                &config.rules, &item.category, &item.title)
+                .unwrap_or(&config.account_expense);
+            let mut posting = Posting::newExpense(account, item.sub_total);
+            posting.meta.push(("title".to_owned(),
+                               MetaValue::Text(item.title.clone())));
+            posting.meta.push(("category".to_owned(),
+                               MetaValue::Text(item.category.clone())));
+            posting.meta.push(("quantity".to_owned(),
+                               MetaValue::Plain(item.quantity.to_string())));
+            postings.push(posting);
+        }
+        transaction::allocate(&mut postings, self.shipping, self.tax, config,
+                              self.marketplace.decimalPlaces());
+        if !self.promotion.is_zero()
+        {
+            postings.push(Posting::new(&config.account_promotion,
+                                       -self.promotion));
+        }
+        let gift_card = self.giftCardAmount();
+        if !gift_card.is_zero()
+        {
+            postings.push(Posting::new(&config.account_gift_card, -gift_card));
+        }
+        let charged = self.charged();
+        if !charged.is_zero() || gift_card.is_zero()
+        {
+            postings.push(Posting::new(payment::creditAccount(&self.instruments, config), -charged));
+        }
+        postings
     }
 
-    pub fn beancountEntry(&self, config: &Config) -> String
+    // How much is paid by gift card. Without a gift card amount in the
+    // report, it is everything if the gift card is the only payment
+    // instrument, and nothing otherwise.
+    fn giftCardAmount(&self) -> Decimal
     {
-        let mut lines = Vec::new();
-        lines.push(
-            format!(r#"{} * "Amazon" """#, self.date.format("%F").to_string()));
-        lines.push(
-            format!("  order-number: {}", self.order_number));
-        lines.push(
-            format!("  {}", config.account_expense));
-        if self.shipping != 0.0
+        if let Some(amount) = self.gift_card
+        {
+            amount
+        }
+        else if !self.instruments.is_empty()
+            && self.instruments.iter().all(Instrument::isGiftCard)
         {
-            lines.push(
-                format!("  {} {:.2} USD", config.account_shipping,
-                        self.shipping));
+            self.sub_total
         }
-        if self.tax != 0.0
+        else
+        {
+            Decimal::ZERO
+        }
+    }
+
+    // A warning if the order is paid partly by gift card, but the
+    // report does not say how much. All of it is then charged to the
+    // other payment instrument, which is likely wrong.
+    pub fn giftCardWarning(&self, config: &Config) -> Option<String>
+    {
+        if self.gift_card.is_some()
+            || !self.instruments.iter().any(Instrument::isGiftCard)
+            || self.instruments.iter().all(Instrument::isGiftCard)
         {
-            lines.push(
-                format!("  {} {:.2} USD", config.account_tax, self.tax));
+            return None;
+        }
+        Some(format!("order {} is paid partly by gift card, but the report has \
+                      no gift card amount. All of it is charged to {}.",
+                     self.order_number,
+                     payment::creditAccount(&self.instruments, config)))
+    }
+
+    // The amount charged to the payment instrument, i.e. the total less
+    // what is paid by gift card.
+    pub fn charged(&self) -> Decimal
+    {
+        self.sub_total - self.giftCardAmount()
+    }
+
+    // The payment instruments of the order, if the report has them.
+    pub fn instruments(&self) -> &[Instrument]
+    {
+        &self.instruments
+    }
+
+    // When the order is charged: the shipment date if the order is a
+    // shipment, and the order date otherwise.
+    pub fn chargeDate(&self) -> NaiveDate
+    {
+        self.shipment_date.unwrap_or(self.date)
+    }
+
+    // A shipment is dated by its shipment date. All the shipments of
+    // an order are linked by the order number.
+    pub fn transaction(&self, config: &Config) -> Transaction
+    {
+        let mut meta = vec![("order-number".to_owned(),
+                             MetaValue::Plain(self.order_number.clone()))];
+        if let Some(date) = self.shipment_date
+        {
+            meta.push(("shipment-date".to_owned(),
+                       MetaValue::Plain(date.format("%F").to_string())));
+        }
+        Transaction {
+            date: self.chargeDate(),
+            narration: String::new(),
+            links: vec![self.order_number.clone()],
+            meta,
+            postings: self.postings(config),
+            marketplace: self.marketplace,
+            url: self.url(),
         }
-        lines.push(
-            format!("  {} -{:.2} USD", config.account_credit, self.sub_total));
-        lines.join("\n")
     }
 }
 
@@ -151,5 +355,187 @@ impl std::ops::AddAssign for Order
         self.shipping += other.shipping;
         self.sub_total += other.sub_total;
         self.tax += other.tax;
+        self.items.extend(other.items);
+        self.promotion += other.promotion;
+        self.gift_card = match (self.gift_card, other.gift_card)
+        {
+            (None, None) => None,
+            (a, b) => Some(a.unwrap_or(Decimal::ZERO) + b.unwrap_or(Decimal::ZERO)),
+        };
+        if self.instruments.is_empty()
+        {
+            self.instruments = other.instruments;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::config::Allocation;
+    use crate::grouping;
+
+    fn dec(s: &str) -> Decimal
+    {
+        s.parse().unwrap()
+    }
+
+    fn ymd(y: i32, m: u32, d: u32) -> NaiveDate
+    {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn readGrouped(data: &str, config: &Config) -> Vec<Order>
+    {
+        let (orders, conflicts) = grouping::readOrders(data.as_bytes(), config)
+            .unwrap();
+        assert!(conflicts.is_empty());
+        orders
+    }
+
+    #[test]
+    fn testReadOrders()
+    {
+        let orders = readGrouped(include_str!("../testdata/orders.csv"),
+                                 &Config::default());
+        assert_eq!(orders.len(), 3);
+        assert_eq!(orders[0].order_number, "111-0000001-0000001");
+        assert_eq!(orders[0].date, ymd(2021, 1, 5));
+        assert_eq!(orders[0].shipment_date, Some(ymd(2021, 1, 6)));
+        assert_eq!(orders[1].shipment_date, Some(ymd(2021, 1, 8)));
+        assert_eq!(orders[1].total(), dec("28.10"));
+        assert_eq!(orders[1].shipping(), dec("5.99"));
+        assert_eq!(orders[1].tax(), dec("2.01"));
+        assert_eq!(orders[2].shipment_date, None);
+        assert_eq!(orders[2].total(), dec("1296"));
+        assert_eq!(orders[2].tax(), dec("96"));
+    }
+
+    #[test]
+    fn testMergeItems()
+    {
+        let orders = readGrouped(include_str!("../testdata/items.csv"),
+                                 &Config::default());
+        assert_eq!(orders.len(), 2);
+        let order = &orders[0];
+        assert_eq!(order.order_number, "111-0000001-0000001");
+        assert_eq!(order.total(), dec("32.85"));
+        assert_eq!(order.tax(), dec("2.85"));
+        let titles: Vec<&str> = order.items().iter()
+            .map(|i| i.title.as_str()).collect();
+        assert_eq!(titles, vec!["The \"Rust\" Book", "USB-C cable"]);
+        assert_eq!(order.items()[1].quantity, 2);
+        assert_eq!(orders[1].items().len(), 1);
+        assert_eq!(orders[1].total(), dec("30"));
+
+        let t = order.transaction(&Config::default());
+        assert!(t.checkBalance().is_ok());
+        assert_eq!(t.postings.len(), 4);
+    }
+
+    #[test]
+    fn testProportionalTax()
+    {
+        let config = Config { tax_allocation: Allocation::Proportional,
+                              ..Config::default() };
+        let orders = readGrouped(include_str!("../testdata/items.csv"), &config);
+        let t = orders[0].transaction(&config);
+        assert!(t.checkBalance().is_ok());
+        assert!(t.postings.iter().all(|p| p.account != config.account_tax));
+        let amounts: Vec<Decimal> = t.postings.iter().map(|p| p.amount).collect();
+        assert_eq!(amounts, vec![dec("21.90"), dec("10.95"), dec("-32.85")]);
+    }
+
+    #[test]
+    fn testReadMarketplacePrices()
+    {
+        let config = Config { marketplace: Marketplace::Germany, ..Config::default() };
+        let orders = readGrouped(include_str!("../testdata/de.csv"), &config);
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0].total(), dec("1234.56"));
+        assert_eq!(orders[0].tax(), dec("197.70"));
+        assert_eq!(orders[0].shipping(), dec("3.99"));
+    }
+
+    const GIFT_CARD_HEADER: &str = "Order Date,Order ID,Payment Instrument Type,\
+                                    Shipping Charge,Tax Charged,Total Promotions,\
+                                    Total Charged";
+
+    fn creditPostings(order: &Order, config: &Config) -> Vec<(String, Decimal)>
+    {
+        let t = order.transaction(config);
+        assert!(t.checkBalance().is_ok());
+        t.postings.iter().filter(|p| !p.expense && p.amount.is_sign_negative())
+            .map(|p| (p.account.clone(), p.amount)).collect()
+    }
+
+    #[test]
+    fn testGiftCardOnly()
+    {
+        let config = Config::default();
+        let data = format!("{}\n01/05/21,1,Gift Certificate/Card,$0,$1,$0,$11\n",
+                           GIFT_CARD_HEADER);
+        let orders = readGrouped(&data, &config);
+        assert_eq!(orders[0].charged(), Decimal::ZERO);
+        assert!(orders[0].giftCardWarning(&config).is_none());
+        assert_eq!(creditPostings(&orders[0], &config),
+                   vec![(config.account_gift_card.clone(), dec("-11"))]);
+    }
+
+    #[test]
+    fn testGiftCardMixed()
+    {
+        let config = Config::default();
+        let data = format!("{},Gift Card Amount\n\
+                            01/05/21,1,Visa - 1234 and Gift Certificate/Card,\
+                            $0,$1,$0,$11,$4\n", GIFT_CARD_HEADER);
+        let orders = readGrouped(&data, &config);
+        assert_eq!(orders[0].charged(), dec("7"));
+        assert!(orders[0].giftCardWarning(&config).is_none());
+        assert_eq!(creditPostings(&orders[0], &config),
+                   vec![(config.account_gift_card.clone(), dec("-4")),
+                        (config.account_credit.clone(), dec("-7"))]);
+
+        // Without the amount, everything is charged to the card, with
+        // a warning.
+        let data = format!("{}\n01/05/21,1,Visa - 1234 and Gift Certificate/Card,\
+                            $0,$1,$0,$11\n", GIFT_CARD_HEADER);
+        let orders = readGrouped(&data, &config);
+        assert_eq!(orders[0].charged(), dec("11"));
+        assert!(orders[0].giftCardWarning(&config).unwrap().contains(
+            &config.account_credit));
+        assert_eq!(creditPostings(&orders[0], &config),
+                   vec![(config.account_credit.clone(), dec("-11"))]);
+    }
+
+    #[test]
+    fn testPromotion()
+    {
+        let config = Config::default();
+        let data = format!("{}\n01/05/21,1,Visa - 1234,$2,$1,$5,$11\n",
+                           GIFT_CARD_HEADER);
+        let orders = readGrouped(&data, &config);
+        let t = orders[0].transaction(&config);
+        assert!(t.checkBalance().is_ok());
+        // The expense is before the promotion.
+        assert_eq!(t.postings[0].amount, dec("13"));
+        assert_eq!(creditPostings(&orders[0], &config),
+                   vec![(config.account_promotion.clone(), dec("-5")),
+                        (config.account_credit.clone(), dec("-11"))]);
+    }
+
+    #[test]
+    fn testReadErrors()
+    {
+        let config = Config::default();
+        let missing = "Order Date,Order ID\n01/05/21,1\n";
+        assert!(Order::readCSVFrom(missing.as_bytes(), &config, |_| ()).is_err());
+
+        let bad_date = "Order Date,Order ID,Shipping Charge,Tax Charged,Total Charged\n\
+                        01/05/21,1,$0,$0,$1\n\
+                        2021-01-06,2,$0,$0,$1\n";
+        assert_eq!(Order::readCSVFrom(bad_date.as_bytes(), &config, |_| ()),
+                   Err(Error::DateError { row: 3, text: String::from("2021-01-06") }));
     }
 }
@@ -0,0 +1,94 @@
+use serde::Deserialize;
+
+use crate::config::Config;
+
+// A payment instrument, as written in the reports. For example
+// “Visa - 1234” is a Visa card ending with 1234.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Instrument
+{
+    pub kind: String,
+    pub last_four: Option<String>,
+}
+
+impl Instrument
+{
+    pub fn isGiftCard(&self) -> bool
+    {
+        let kind = self.kind.to_lowercase();
+        kind.contains("gift certificate") || kind.contains("gift card")
+    }
+
+    fn parse(s: &str) -> Self
+    {
+        match s.rsplit_once(" - ")
+        {
+            Some((kind, digits)) => Self {
+                kind: kind.trim().to_owned(),
+                last_four: Some(digits.trim().to_owned()),
+            },
+            None => Self { kind: s.trim().to_owned(), last_four: None },
+        }
+    }
+}
+
+// Parse a “Payment Instrument Type” field. An order paid by more than
+// one instrument has them joined by “and”.
+pub fn parseInstruments(s: &str) -> Vec<Instrument>
+{
+    s.split(" and ").map(str::trim).filter(|part| !part.is_empty())
+        .map(Instrument::parse).collect()
+}
+
+// Map a payment instrument to a liability or asset account. The type
+// is matched case-insensitively; either the type or the last four
+// digits can be left out.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PaymentAccount
+{
+    #[serde(default, rename = "type")]
+    pub kind: Option<String>,
+    #[serde(default)]
+    pub last_four: Option<String>,
+    pub account: String,
+}
+
+impl PaymentAccount
+{
+    fn matches(&self, instrument: &Instrument) -> bool
+    {
+        if let Some(kind) = &self.kind
+        {
+            if !kind.eq_ignore_ascii_case(&instrument.kind)
+            {
+                return false;
+            }
+        }
+        if let Some(digits) = &self.last_four
+        {
+            if instrument.last_four.as_ref() != Some(digits)
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// Return the account of the first mapping that matches the instrument,
+// if any.
+pub fn findAccount<'a>(accounts: &'a [PaymentAccount], instrument: &Instrument)
+                       -> Option<&'a str>
+{
+    accounts.iter().find(|a| a.matches(instrument))
+        .map(|a| a.account.as_str())
+}
+
+// The account that pays with `instruments`, other than gift cards.
+pub fn creditAccount<'a>(instruments: &[Instrument], config: &'a Config) -> &'a str
+{
+    instruments.iter().find(|i| !i.isGiftCard())
+        .and_then(|i| findAccount(&config.payment, i))
+        .unwrap_or(&config.account_credit)
+}
@@ -0,0 +1,270 @@
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+
+use crate::error::Error;
+use crate::config::{ChargeSign, Config};
+use crate::columns::{Column, ColumnMap};
+use crate::date::DateParser;
+use crate::order_record::Order;
+
+// A charge in the transaction CSV from the bank.
+pub struct Charge
+{
+    pub date: NaiveDate,
+    // The amount charged, which is positive no matter which sign the
+    // bank uses for charges.
+    pub amount: Decimal,
+    pub description: String,
+}
+
+impl Charge
+{
+    const COLUMNS: &'static [Column] = &[
+        Column::BankDate, Column::BankAmount, Column::BankDescription];
+
+    // Read the charges from the bank CSV. Only those whose description
+    // matches the bank filter in the config are returned, and credits
+    // (amounts of the other sign than `bank_charge_sign`) are skipped.
+    pub fn fromCSV(filename: &str, config: &Config) -> Result<Vec<Self>, Error>
+    {
+        let file = std::fs::File::open(filename).map_err(
+            |_| rterr!("Failed to read CSV file: {}", filename))?;
+        Self::fromCSVFrom(file, config).map_err(|e| match e
+        {
+            Error::RuntimeError(msg) => rterr!("{}: {}", filename, msg),
+            e => e,
+        })
+    }
+
+    // Like fromCSV(), but read the bank CSV from `reader`.
+    pub fn fromCSVFrom<R: std::io::Read>(reader: R, config: &Config)
+                                         -> Result<Vec<Self>, Error>
+    {
+        let mut csv_reader = csv::Reader::from_reader(reader);
+        let header = csv_reader.headers().map_err(
+            |_| rterr!("Failed to read CSV header"))?.clone();
+        let columns = ColumnMap::fromHeader(&header, &config.columns);
+        columns.require(Self::COLUMNS)?;
+
+        let mut dates = DateParser::new();
+        let mut rows = Vec::new();
+        for row_maybe in csv_reader.records()
+        {
+            let row = row_maybe.map_err(
+                |_| rterr!("Failed to read a CSV row."))?;
+            let description = columns.get(&row, Column::BankDescription)
+                .unwrap_or("").to_owned();
+            if !config.bank_filter.is_match(&description)
+            {
+                continue;
+            }
+            let line = row.position().map(|p| p.line()).unwrap_or(0);
+            let date = dates.parse(
+                columns.get(&row, Column::BankDate).unwrap_or(""), line)?;
+            let amount = columns.get(&row, Column::BankAmount).unwrap_or("");
+            let amount = config.marketplace.parsePrice(amount).map_err(
+                |e| rterr!("Failed to parse amount at row {}: {}", line, e))?;
+            rows.push(Self { date, amount, description });
+        }
+
+        let negative = match config.bank_charge_sign
+        {
+            ChargeSign::Negative => true,
+            ChargeSign::Positive => false,
+            ChargeSign::Auto => {
+                let negatives = rows.iter().filter(|c| c.amount < Decimal::ZERO)
+                    .count();
+                negatives * 2 >= rows.len()
+            },
+        };
+        let sign = if negative { Decimal::NEGATIVE_ONE } else { Decimal::ONE };
+        Ok(rows.into_iter()
+           .map(|c| Self { amount: c.amount * sign, ..c })
+           .filter(|c| c.amount > Decimal::ZERO)
+           .collect())
+    }
+}
+
+// The result of reconciliation. The numbers are indices into the
+// orders and the charges.
+pub struct Reconciliation
+{
+    pub matched: Vec<(usize, usize)>,
+    pub unmatched_orders: Vec<usize>,
+    pub unmatched_charges: Vec<usize>,
+}
+
+// Match each order with a charge of the same amount, that is at most
+// `window` days away from the date the order is charged, i.e. the
+// shipment date if there is one. If there are more than one
+// such charges, the closest one is used. A charge is matched to at
+// most one order. Orders paid entirely by gift card are left out.
+pub fn reconcile(orders: &[Order], charges: &[Charge], window: i64)
+                 -> Reconciliation
+{
+    let mut used = vec![false; charges.len()];
+    let mut result = Reconciliation {
+        matched: Vec::new(),
+        unmatched_orders: Vec::new(),
+        unmatched_charges: Vec::new(),
+    };
+
+    let mut order_indices: Vec<usize> = (0..orders.len()).collect();
+    order_indices.sort_by_key(|i| orders[*i].chargeDate());
+    for i in order_indices
+    {
+        let order = &orders[i];
+        let amount = order.charged();
+        if amount.is_zero()
+        {
+            continue;
+        }
+        let best = charges.iter().enumerate()
+            .filter(|(j, charge)| !used[*j] && charge.amount == amount)
+            .map(|(j, charge)| {
+                (j, (charge.date - order.chargeDate()).num_days().abs())
+            })
+            .filter(|(_, distance)| *distance <= window)
+            .min_by_key(|(_, distance)| *distance);
+        match best
+        {
+            Some((j, _)) => {
+                used[j] = true;
+                result.matched.push((i, j));
+            },
+            None => result.unmatched_orders.push(i),
+        }
+    }
+    result.matched.sort();
+    result.unmatched_orders.sort();
+    result.unmatched_charges = (0..charges.len()).filter(|j| !used[*j])
+        .collect();
+    result
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::grouping;
+
+    fn readOrders(data: &str) -> Vec<Order>
+    {
+        grouping::readOrders(data.as_bytes(), &Config::default()).unwrap().0
+    }
+
+    fn readCharges(data: &str) -> Vec<Charge>
+    {
+        Charge::fromCSVFrom(data.as_bytes(), &Config::default()).unwrap()
+    }
+
+    const ORDERS: &str = "Order Date,Order ID,Shipping Charge,Tax Charged,Total Charged
+01/10/21,1,$0,$0,$10.00
+01/10/21,2,$0,$0,$20.00
+";
+
+    #[test]
+    fn testReadCharges()
+    {
+        let data = "Date,Description,Amount
+01/11/2021,AMZN Mktp US,-$10.00
+01/11/2021,Coffee shop,-$10.00
+01/12/2021,Amazon.com,-$20.00
+01/15/2021,Amazon.com,$10.00
+";
+        // The coffee shop is not matched by the bank filter, and the
+        // refund is a credit.
+        let charges = readCharges(data);
+        assert_eq!(charges.len(), 2);
+        assert_eq!(charges[0].amount, "10".parse().unwrap());
+        assert_eq!(charges[0].description, "AMZN Mktp US");
+        assert_eq!(charges[1].amount, "20".parse().unwrap());
+
+        let config = Config { bank_charge_sign: ChargeSign::Positive,
+                              ..Config::default() };
+        let charges = Charge::fromCSVFrom(data.as_bytes(), &config).unwrap();
+        assert_eq!(charges.len(), 1);
+        assert_eq!(charges[0].date, NaiveDate::from_ymd_opt(2021, 1, 15).unwrap());
+
+        // Most amounts are positive, so those are the charges.
+        let charges = readCharges("Date,Description,Debit
+01/11/2021,AMAZON,10.00
+01/12/2021,AMAZON,20.00
+01/15/2021,AMAZON,-10.00
+");
+        assert_eq!(charges.len(), 2);
+        assert_eq!(charges[1].amount, "20".parse().unwrap());
+
+        let config = Config { bank_filter: regex::Regex::new("Coffee").unwrap(),
+                              ..Config::default() };
+        let data = "Date,Description,Amount\n01/11/2021,Coffee shop,$1\n";
+        assert_eq!(Charge::fromCSVFrom(data.as_bytes(), &config).unwrap().len(), 1);
+
+        assert!(Charge::fromCSVFrom("Date,Amount\n".as_bytes(), &config).is_err());
+    }
+
+    #[test]
+    fn testWindow()
+    {
+        let orders = readOrders(ORDERS);
+        let charges = readCharges("Date,Description,Amount
+01/17/2021,AMAZON,-10.00
+01/18/2021,AMAZON,-20.00
+");
+        let result = reconcile(&orders, &charges, 7);
+        assert_eq!(result.matched, vec![(0, 0)]);
+        assert_eq!(result.unmatched_orders, vec![1]);
+        assert_eq!(result.unmatched_charges, vec![1]);
+
+        let result = reconcile(&orders, &charges, 8);
+        assert_eq!(result.matched, vec![(0, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn testRefundNotMatched()
+    {
+        // The refund of order 1 is as much as order 1, and closer to it
+        // than the charge.
+        let orders = readOrders(ORDERS);
+        let charges = readCharges("Date,Description,Amount
+01/10/2021,AMAZON,10.00
+01/15/2021,AMAZON,-10.00
+01/15/2021,AMAZON,-20.00
+");
+        let result = reconcile(&orders, &charges, 7);
+        assert_eq!(result.matched, vec![(0, 0), (1, 1)]);
+        assert!(result.unmatched_charges.is_empty());
+    }
+
+    #[test]
+    fn testNearestCharge()
+    {
+        let orders = readOrders(ORDERS);
+        let charges = readCharges("Date,Description,Amount
+01/14/2021,AMAZON,-10.00
+01/09/2021,AMAZON,-10.00
+01/12/2021,AMAZON,-10.00
+");
+        let result = reconcile(&orders, &charges, 7);
+        assert_eq!(result.matched, vec![(0, 1)]);
+        assert_eq!(result.unmatched_charges, vec![0, 2]);
+    }
+
+    #[test]
+    fn testShipmentDate()
+    {
+        // The second shipment is 10 days after the order.
+        let orders = readOrders("\
+Order Date,Order ID,Shipment Date,Shipping Charge,Tax Charged,Total Charged
+01/10/21,1,01/11/21,$0,$0,$10.00
+01/10/21,1,01/20/21,$0,$0,$20.00
+");
+        let charges = readCharges("Date,Description,Amount
+01/21/2021,AMAZON,-20.00
+01/11/2021,AMAZON,-10.00
+");
+        let result = reconcile(&orders, &charges, 3);
+        assert_eq!(result.matched, vec![(0, 1), (1, 0)]);
+        assert!(result.unmatched_orders.is_empty());
+    }
+}
@@ -0,0 +1,271 @@
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+
+use crate::error::Error;
+use crate::config::Config;
+use crate::columns::{Column, ColumnMap};
+use crate::date::DateParser;
+use crate::marketplace::Marketplace;
+use crate::order_record::Order;
+use crate::payment::{self, Instrument};
+use crate::rules;
+use crate::transaction::{self, MetaValue, Posting, Transaction};
+
+// A refunded item.
+#[derive(Clone)]
+struct RefundItem
+{
+    title: String,
+    category: String,
+    // Refunded amount of this item, without tax and shipping.
+    amount: Decimal,
+}
+
+// All the money refunded for an order on a day.
+#[derive(Clone)]
+pub struct Refund
+{
+    pub date: NaiveDate,
+    pub order_number: String,
+    // The line in the report of the first row of this refund.
+    pub line: u64,
+    items: Vec<RefundItem>,
+    tax: Decimal,
+    shipping: Decimal,
+    // The payment instruments of the refunded order, from the report
+    // or from the order itself.
+    instruments: Vec<Instrument>,
+    pub marketplace: Marketplace,
+}
+
+impl Refund
+{
+    pub const COLUMNS: &'static [Column] = &[
+        Column::OrderNumber, Column::Title, Column::Category,
+        Column::RefundDate, Column::RefundAmount, Column::RefundTax];
+
+    // In the refunds report, the refund amount does not include tax.
+    // If the report has the refunded shipping, it is included in the
+    // refund amount.
+    fn fromCSVRow(row: &csv::StringRecord, line: u64, date: NaiveDate,
+                  columns: &ColumnMap, marketplace: Marketplace)
+                  -> Result<Self, Error>
+    {
+        let get = |column: Column| columns.get(row, column).ok_or_else(
+            || rterr!("Missing column {}", column.name()));
+        let price = |column: Column| marketplace.parsePrice(get(column)?).map_err(
+            |e| rterr!("Failed to parse {}: {}", column.name(), e));
+
+        // An empty cell is the same as no column.
+        let shipping = match columns.get(row, Column::RefundShipping)
+        {
+            Some(s) if !s.trim().is_empty() => marketplace.parsePrice(s).map_err(
+                |e| rterr!("Failed to parse refund_shipping: {}", e))?,
+            _ => Decimal::ZERO,
+        };
+        let item = RefundItem {
+            title: get(Column::Title)?.to_owned(),
+            category: get(Column::Category)?.to_owned(),
+            amount: price(Column::RefundAmount)? - shipping,
+        };
+        Ok(Self {
+            date,
+            order_number: get(Column::OrderNumber)?.to_owned(),
+            line,
+            items: vec![item],
+            tax: price(Column::RefundTax)?,
+            shipping,
+            instruments: payment::parseInstruments(
+                columns.get(row, Column::Payment).unwrap_or("")),
+            marketplace,
+        })
+    }
+
+    // Read a refunds report. Rows of the same order refunded on the
+    // same day are combined into one refund.
+    pub fn fromCSV(filename: &str, config: &Config) -> Result<Vec<Self>, Error>
+    {
+        let file = std::fs::File::open(filename).map_err(
+            |_| rterr!("Failed to read CSV file: {}", filename))?;
+        Self::fromCSVFrom(file, config).map_err(|e| match e
+        {
+            Error::RuntimeError(msg) => rterr!("{}: {}", filename, msg),
+            e => e,
+        })
+    }
+
+    // Like fromCSV(), but read the report from `reader`.
+    pub fn fromCSVFrom<R: std::io::Read>(reader: R, config: &Config)
+                                         -> Result<Vec<Self>, Error>
+    {
+        let mut csv_reader = csv::Reader::from_reader(reader);
+        let header = csv_reader.headers().map_err(
+            |_| rterr!("Failed to read CSV header"))?.clone();
+        let columns = ColumnMap::fromHeader(&header, &config.columns);
+        columns.require(Self::COLUMNS)?;
+
+        let mut dates = DateParser::new();
+        let mut refunds: Vec<Self> = Vec::new();
+        for row_maybe in csv_reader.records()
+        {
+            let row = row_maybe.map_err(
+                |_| rterr!("Failed to read a CSV row."))?;
+            let line = row.position().map(|p| p.line()).unwrap_or(0);
+            let date = dates.parse(
+                columns.get(&row, Column::RefundDate).unwrap_or(""), line)?;
+            let refund = Self::fromCSVRow(&row, line, date, &columns,
+                                          config.marketplace)
+                .map_err(|e| rterr!("{}: {:?}", e, row))?;
+            match refunds.iter_mut().find(
+                |r| r.order_number == refund.order_number && r.date == date)
+            {
+                Some(r) => {
+                    r.items.extend(refund.items);
+                    r.tax += refund.tax;
+                    r.shipping += refund.shipping;
+                },
+                None => refunds.push(refund),
+            }
+        }
+        Ok(refunds)
+    }
+
+    // Take the payment instruments from the refunded order in
+    // `orders`, unless the refunds report has them.
+    pub fn matchOrder(&mut self, orders: &[Order])
+    {
+        if !self.instruments.is_empty()
+        {
+            return;
+        }
+        if let Some(order) = orders.iter().find(
+            |o| o.order_number == self.order_number && !o.instruments().is_empty())
+        {
+            self.instruments = order.instruments().to_vec();
+        }
+    }
+
+    // The money goes back to the gift card if the order was paid only
+    // by gift card, and to the credit account of the order otherwise.
+    fn creditAccount<'a>(&self, config: &'a Config) -> &'a str
+    {
+        if !self.instruments.is_empty() && self.instruments.iter().all(Instrument::isGiftCard)
+        {
+            &config.account_gift_card
+        }
+        else
+        {
+            payment::creditAccount(&self.instruments, config)
+        }
+    }
+
+    pub fn url(&self) -> String
+    {
+        format!("https://{}/gp/your-account/order-details?orderID={}",
+                self.marketplace.domain(), self.order_number)
+    }
+
+    // The reverse of the order: the expense, shipping and tax accounts
+    // are credited, and the account that paid gets the money back. Shipping
+    // and tax are allocated like in the order.
+    pub fn postings(&self, config: &Config) -> Vec<Posting>
+    {
+        let mut postings = Vec::new();
+        for item in &self.items
+        {
+            let account = rules::findAccount(
+                &config.rules, &item.category, &item.title)
+                .unwrap_or(&config.account_expense);
+            let mut posting = Posting::newExpense(account, -item.amount);
+            posting.meta.push(("title".to_owned(),
+                               MetaValue::Text(item.title.clone())));
+            postings.push(posting);
+        }
+        transaction::allocate(&mut postings, -self.shipping, -self.tax, config,
+                              self.marketplace.decimalPlaces());
+        let total: Decimal = self.items.iter().map(|item| item.amount).sum();
+        postings.push(Posting::new(self.creditAccount(config),
+                                   total + self.shipping + self.tax));
+        postings
+    }
+
+    // The transaction carries the number of the refunded order, both as
+    // metadata and as a link, and is marked as a refund so that it is
+    // not mistaken for the order itself.
+    pub fn transaction(&self, config: &Config) -> Transaction
+    {
+        Transaction {
+            date: self.date,
+            narration: String::from("Refund"),
+            links: vec![self.order_number.clone()],
+            meta: vec![("order-number".to_owned(),
+                        MetaValue::Plain(self.order_number.clone())),
+                       ("refund".to_owned(), MetaValue::Plain("TRUE".to_owned()))],
+            postings: self.postings(config),
+            marketplace: self.marketplace,
+            url: self.url(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::grouping;
+    use crate::payment::PaymentAccount;
+
+    const REFUNDS: &str = "\
+Order ID,Title,Category,Refund Date,Refund Amount,Refund Tax Amount
+111-0000001-0000001,USB-C cable,Electronics,01/20/21,$10.00,$0.95
+";
+
+    fn config() -> Config
+    {
+        let mut config = Config::default();
+        config.payment.push(PaymentAccount {
+            kind: Some(String::from("visa")), last_four: Some(String::from("1234")),
+            account: String::from("Liabilities:Credit:Visa") });
+        config
+    }
+
+    fn creditPosting(t: &Transaction) -> &Posting
+    {
+        t.postings.last().unwrap()
+    }
+
+    #[test]
+    fn testRefundToOrderInstrument()
+    {
+        let config = config();
+        let orders = grouping::readOrders(
+            include_str!("../testdata/orders.csv").as_bytes(), &config).unwrap().0;
+
+        let mut refunds = Refund::fromCSVFrom(REFUNDS.as_bytes(), &config).unwrap();
+        assert_eq!(refunds.len(), 1);
+        let t = refunds[0].transaction(&config);
+        assert_eq!(creditPosting(&t).account, config.account_credit);
+
+        refunds[0].matchOrder(&orders);
+        let t = refunds[0].transaction(&config);
+        assert!(t.checkBalance().is_ok());
+        assert_eq!(creditPosting(&t).account, "Liabilities:Credit:Visa");
+        assert_eq!(creditPosting(&t).amount, "10.95".parse().unwrap());
+    }
+
+    #[test]
+    fn testRefundToReportInstrument()
+    {
+        let config = config();
+        let data = "\
+Order ID,Title,Category,Refund Date,Refund Amount,Refund Tax Amount,Payment Instrument Type
+111-0000001-0000001,USB-C cable,Electronics,01/20/21,$10.00,$0.95,Visa - 1234
+111-0000002-0000002,Book,Paperback,01/21/21,$5.00,$0.00,Gift Certificate/Card
+";
+        let refunds = Refund::fromCSVFrom(data.as_bytes(), &config).unwrap();
+        assert_eq!(creditPosting(&refunds[0].transaction(&config)).account,
+                   "Liabilities:Credit:Visa");
+        assert_eq!(creditPosting(&refunds[1].transaction(&config)).account,
+                   config.account_gift_card);
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::BTreeSet;
+
+use chrono::NaiveDate;
+
+use crate::error::Error;
+use crate::transaction::{MetaValue, Transaction};
+
+// The output formats, by their names on the command line.
+pub const FORMATS: &[&str] = &["beancount", "ledger", "hledger"];
+
+// Turn transactions into the text of an accounting program.
+pub trait Renderer
+{
+    // The beginning of a generated file, which declares the
+    // currencies, except those in `declared`.
+    fn header(&self, transactions: &[Transaction], declared: &BTreeSet<String>)
+              -> String;
+    // Render one transaction. It is an error if the transaction does
+    // not balance.
+    fn entry(&self, t: &Transaction) -> Result<String, Error>;
+}
+
+// Return the renderer of a format in FORMATS.
+pub fn renderer(format: &str) -> Result<Box<dyn Renderer>, Error>
+{
+    match format
+    {
+        "beancount" => Ok(Box::new(Beancount)),
+        "ledger" => Ok(Box::new(Ledger)),
+        "hledger" => Ok(Box::new(HLedger)),
+        _ => Err(rterr!("Unknown output format: {}", format)),
+    }
+}
+
+fn checkBalance(t: &Transaction) -> Result<(), Error>
+{
+    t.checkBalance().map_err(
+        |e| rterr!("Transaction on {} ({}): {}", t.date, t.url, e))
+}
+
+// The currencies used in the transactions that are not in `declared`,
+// each with the date of the earliest transaction in that currency.
+fn currencies(transactions: &[Transaction], declared: &BTreeSet<String>)
+              -> Vec<(&'static str, usize, NaiveDate)>
+{
+    let mut result: Vec<(&'static str, usize, NaiveDate)> = Vec::new();
+    for t in transactions
+    {
+        let currency = t.marketplace.currency();
+        if declared.contains(currency)
+        {
+            continue;
+        }
+        match result.iter_mut().find(|(c, _, _)| *c == currency)
+        {
+            Some((_, _, date)) => *date = (*date).min(t.date),
+            None => result.push((currency, t.marketplace.decimalPlaces(), t.date)),
+        }
+    }
+    result
+}
+
+fn amount(t: &Transaction, value: &rust_decimal::Decimal) -> String
+{
+    format!("{:.*} {}", t.marketplace.decimalPlaces(), t.rounded(*value),
+            t.marketplace.currency())
+}
+
+pub struct Beancount;
+
+// Quote a string as a Beancount string literal.
+fn quote(s: &str) -> String
+{
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn beancountMeta(value: &MetaValue) -> String
+{
+    match value
+    {
+        MetaValue::Text(s) => quote(s),
+        MetaValue::Plain(s) => s.clone(),
+    }
+}
+
+impl Renderer for Beancount
+{
+    // Each currency is declared at the date of the earliest transaction
+    // in that currency. There are no options, which Beancount ignores
+    // in included files anyway.
+    fn header(&self, transactions: &[Transaction], declared: &BTreeSet<String>)
+              -> String
+    {
+        let mut lines = vec![String::from(";; Generated by amazon-beancount.")];
+        for (currency, _, date) in currencies(transactions, declared)
+        {
+            lines.push(format!("{} commodity {}", date.format("%F"), currency));
+        }
+        lines.join("\n")
+    }
+
+    fn entry(&self, t: &Transaction) -> Result<String, Error>
+    {
+        checkBalance(t)?;
+
+        let mut lines = Vec::new();
+        let mut header = format!(r#"{} * "Amazon" {}"#, t.date.format("%F"),
+                                 quote(&t.narration));
+        for link in &t.links
+        {
+            header.push_str(" ^");
+            header.push_str(link);
+        }
+        lines.push(header);
+        for (key, value) in &t.meta
+        {
+            lines.push(format!("  {}: {}", key, beancountMeta(value)));
+        }
+        for posting in &t.postings
+        {
+            lines.push(format!("  {} {}", posting.account,
+                               amount(t, &posting.amount)));
+            for (key, value) in &posting.meta
+            {
+                lines.push(format!("    {}: {}", key, beancountMeta(value)));
+            }
+        }
+        Ok(lines.join("\n"))
+    }
+}
+
+// Metadata in ledger and hledger are comments like “; key: value”. In
+// hledger a comma ends a tag value, so commas in text are replaced.
+fn ledgerMeta(key: &str, value: &MetaValue, hledger: bool) -> String
+{
+    let value = match value
+    {
+        MetaValue::Text(s) | MetaValue::Plain(s) => s.replace('\n', " "),
+    };
+    if hledger
+    {
+        format!("; {}: {}", key, value.replace(',', ";"))
+    }
+    else
+    {
+        format!("; {}: {}", key, value)
+    }
+}
+
+// The common part of ledger and hledger entries. `first_line` is the
+// date and description.
+fn ledgerEntry(t: &Transaction, first_line: String, hledger: bool)
+               -> Result<String, Error>
+{
+    checkBalance(t)?;
+
+    let mut lines = vec![first_line];
+    for (key, value) in &t.meta
+    {
+        lines.push(format!("    {}", ledgerMeta(key, value, hledger)));
+    }
+    for posting in &t.postings
+    {
+        lines.push(format!("    {}  {}", posting.account,
+                           amount(t, &posting.amount)));
+        for (key, value) in &posting.meta
+        {
+            lines.push(format!("        {}", ledgerMeta(key, value, hledger)));
+        }
+    }
+    Ok(lines.join("\n"))
+}
+
+// The ledger-cli format. The narration becomes a note of the payee.
+pub struct Ledger;
+
+impl Renderer for Ledger
+{
+    fn header(&self, transactions: &[Transaction], declared: &BTreeSet<String>)
+              -> String
+    {
+        let mut lines = vec![String::from("; Generated by amazon-beancount.")];
+        for (currency, _, _) in currencies(transactions, declared)
+        {
+            lines.push(format!("commodity {}", currency));
+        }
+        lines.join("\n")
+    }
+
+    fn entry(&self, t: &Transaction) -> Result<String, Error>
+    {
+        let mut first_line = format!("{} * Amazon", t.date.format("%Y/%m/%d"));
+        if !t.narration.is_empty()
+        {
+            first_line.push_str("  ; ");
+            first_line.push_str(&t.narration);
+        }
+        ledgerEntry(t, first_line, false)
+    }
+}
+
+// The hledger journal format. The narration becomes the note part of
+// the description, as in “payee | note”.
+pub struct HLedger;
+
+impl Renderer for HLedger
+{
+    fn header(&self, transactions: &[Transaction], declared: &BTreeSet<String>)
+              -> String
+    {
+        let mut lines = vec![String::from("; Generated by amazon-beancount.")];
+        for (currency, places, _) in currencies(transactions, declared)
+        {
+            lines.push(format!("commodity {:.*} {}", places, 1000.0, currency));
+        }
+        lines.join("\n")
+    }
+
+    fn entry(&self, t: &Transaction) -> Result<String, Error>
+    {
+        let mut first_line = format!("{} * Amazon", t.date.format("%F"));
+        if !t.narration.is_empty()
+        {
+            first_line.push_str(" | ");
+            first_line.push_str(&t.narration);
+        }
+        ledgerEntry(t, first_line, true)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::marketplace::Marketplace;
+    use crate::transaction::Posting;
+
+    fn transaction() -> Transaction
+    {
+        let mut book = Posting::newExpense("Expenses:Books", "20".parse().unwrap());
+        book.meta.push(("title".to_owned(),
+                        MetaValue::Text(String::from("The \"Rust\" Book, 2nd ed."))));
+        Transaction {
+            date: NaiveDate::from_ymd_opt(2021, 1, 5).unwrap(),
+            narration: String::from("Books"),
+            links: vec![String::from("111-0000001-0000001")],
+            meta: vec![("order-number".to_owned(),
+                        MetaValue::Plain(String::from("111-0000001-0000001")))],
+            postings: vec![book,
+                           Posting::new("Expenses:Taxes", "1.9".parse().unwrap()),
+                           Posting::new("Liabilities:Card", "-21.9".parse().unwrap())],
+            marketplace: Marketplace::US,
+            url: String::new(),
+        }
+    }
+
+    #[test]
+    fn testBeancount()
+    {
+        assert_eq!(Beancount.entry(&transaction()).unwrap(), r#"2021-01-05 * "Amazon" "Books" ^111-0000001-0000001
+  order-number: 111-0000001-0000001
+  Expenses:Books 20.00 USD
+    title: "The \"Rust\" Book, 2nd ed."
+  Expenses:Taxes 1.90 USD
+  Liabilities:Card -21.90 USD"#);
+    }
+
+    #[test]
+    fn testLedger()
+    {
+        assert_eq!(Ledger.entry(&transaction()).unwrap(), "\
+2021/01/05 * Amazon  ; Books
+    ; order-number: 111-0000001-0000001
+    Expenses:Books  20.00 USD
+        ; title: The \"Rust\" Book, 2nd ed.
+    Expenses:Taxes  1.90 USD
+    Liabilities:Card  -21.90 USD");
+    }
+
+    #[test]
+    fn testHLedger()
+    {
+        assert_eq!(HLedger.entry(&transaction()).unwrap(), "\
+2021-01-05 * Amazon | Books
+    ; order-number: 111-0000001-0000001
+    Expenses:Books  20.00 USD
+        ; title: The \"Rust\" Book; 2nd ed.
+    Expenses:Taxes  1.90 USD
+    Liabilities:Card  -21.90 USD");
+    }
+
+    #[test]
+    fn testHeader()
+    {
+        let transactions = [transaction()];
+        let mut declared = BTreeSet::new();
+        assert_eq!(Beancount.header(&transactions, &declared),
+                   ";; Generated by amazon-beancount.\n2021-01-05 commodity USD");
+        assert_eq!(HLedger.header(&transactions, &declared),
+                   "; Generated by amazon-beancount.\ncommodity 1000.00 USD");
+        declared.insert(String::from("USD"));
+        assert_eq!(Beancount.header(&transactions, &declared),
+                   ";; Generated by amazon-beancount.");
+        assert_eq!(Ledger.header(&transactions, &declared),
+                   "; Generated by amazon-beancount.");
+    }
+
+    #[test]
+    fn testUnbalanced()
+    {
+        let mut t = transaction();
+        t.postings.pop();
+        assert!(Beancount.entry(&t).is_err());
+        assert!(Ledger.entry(&t).is_err());
+        assert!(HLedger.entry(&t).is_err());
+    }
+}
@@ -0,0 +1,129 @@
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::error::ReadlineError;
+use rustyline::{Context, Editor, Helper};
+
+use crate::error::Error;
+use crate::transaction::Transaction;
+use crate::render::Renderer;
+
+// Complete account names, when enabled.
+struct AccountCompleter
+{
+    accounts: Vec<String>,
+    enabled: bool,
+}
+
+impl Completer for AccountCompleter
+{
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>)
+                -> rustyline::Result<(usize, Vec<String>)>
+    {
+        if !self.enabled
+        {
+            return Ok((0, Vec::new()));
+        }
+        let prefix = &line[..pos];
+        Ok((0, self.accounts.iter().filter(|a| a.starts_with(prefix))
+            .cloned().collect()))
+    }
+}
+
+impl Hinter for AccountCompleter
+{
+    type Hint = String;
+}
+
+impl Highlighter for AccountCompleter {}
+impl Validator for AccountCompleter {}
+impl Helper for AccountCompleter {}
+
+// What the user decided to do with a transaction.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Decision
+{
+    Accept,
+    Skip,
+    // Stop reviewing. The current transaction is skipped.
+    Quit,
+}
+
+pub struct Reviewer
+{
+    editor: Editor<AccountCompleter, DefaultHistory>,
+}
+
+impl Reviewer
+{
+    // `accounts` are the candidates for completion.
+    pub fn new(accounts: Vec<String>) -> Result<Self, Error>
+    {
+        let mut editor = Editor::new().map_err(
+            |e| rterr!("Failed to initialize terminal: {}", e))?;
+        editor.set_helper(Some(AccountCompleter { accounts, enabled: false }));
+        Ok(Self { editor })
+    }
+
+    // Read a line. Return None at end of input or on Ctrl-C.
+    fn readLine(&mut self, prompt: &str, complete: bool)
+                -> Result<Option<String>, Error>
+    {
+        if let Some(helper) = self.editor.helper_mut()
+        {
+            helper.enabled = complete;
+        }
+        match self.editor.readline(prompt)
+        {
+            Ok(line) => Ok(Some(line.trim().to_owned())),
+            Err(ReadlineError::Eof) | Err(ReadlineError::Interrupted) => Ok(None),
+            Err(e) => Err(rterr!("Failed to read input: {}", e)),
+        }
+    }
+
+    // Show the transaction and let the user change it until it is
+    // accepted or skipped.
+    pub fn review(&mut self, t: &mut Transaction, renderer: &dyn Renderer)
+                  -> Result<Decision, Error>
+    {
+        loop
+        {
+            println!("\n{}", renderer.entry(t)?);
+            println!("\nURL: {}\n", t.url);
+            let command = match self.readLine(
+                "[a]ccept, [s]kip, change [e]xpense account, add [n]arration, [q]uit? ",
+                false)?
+            {
+                Some(c) => c,
+                None => return Ok(Decision::Quit),
+            };
+            match command.as_str()
+            {
+                "a" | "" => return Ok(Decision::Accept),
+                "s" => return Ok(Decision::Skip),
+                "q" => return Ok(Decision::Quit),
+                "e" => {
+                    if let Some(account) = self.readLine(
+                        "Expense account (Tab to complete): ", true)?
+                    {
+                        if !account.is_empty()
+                        {
+                            t.setExpenseAccount(&account);
+                        }
+                    }
+                },
+                "n" => {
+                    if let Some(narration) = self.readLine("Narration: ", false)?
+                    {
+                        t.narration = narration;
+                    }
+                },
+                _ => println!("Unknown command '{}'.", command),
+            }
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+
+pub fn deserializeRegex<'de, D>(deserializer: D) -> Result<Regex, D::Error>
+    where D: Deserializer<'de>
+{
+    let pattern = String::deserialize(deserializer)?;
+    Regex::new(&pattern).map_err(serde::de::Error::custom)
+}
+
+fn deserializeOptRegex<'de, D>(deserializer: D) -> Result<Option<Regex>, D::Error>
+    where D: Deserializer<'de>
+{
+    deserializeRegex(deserializer).map(Some)
+}
+
+// Map an item to an expense account. A rule matches an item if the
+// category is equal to the item category (case-insensitive), and the
+// title regex matches the item title. A rule without either matches
+// all items.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Rule
+{
+    #[serde(default)]
+    pub category: Option<String>,
+    #[serde(default, deserialize_with = "deserializeOptRegex")]
+    pub title: Option<Regex>,
+    pub account: String,
+}
+
+impl Rule
+{
+    fn matches(&self, category: &str, title: &str) -> bool
+    {
+        if let Some(c) = &self.category
+        {
+            if !c.eq_ignore_ascii_case(category.trim())
+            {
+                return false;
+            }
+        }
+        if let Some(r) = &self.title
+        {
+            if !r.is_match(title)
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// Return the account of the first rule that matches the item, if any.
+pub fn findAccount<'a>(rules: &'a [Rule], category: &str, title: &str)
+                       -> Option<&'a str>
+{
+    rules.iter().find(|rule| rule.matches(category, title))
+        .map(|rule| rule.account.as_str())
+}
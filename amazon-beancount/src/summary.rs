@@ -0,0 +1,264 @@
+use std::collections::{BTreeMap, HashSet};
+
+use rust_decimal::Decimal;
+
+use crate::error::Error;
+use crate::config::{Allocation, Config};
+use crate::order_record::Order;
+use crate::transaction::{self, Posting};
+
+// The ways to group orders, by their names on the command line.
+pub const GROUPINGS: &[&str] = &["month", "year", "account"];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Grouping
+{
+    // By the month of the transaction date.
+    Month,
+    Year,
+    // By expense account. An order with items in several accounts
+    // counts in each of them, and its tax and shipping are split in
+    // proportion to the items.
+    Account,
+}
+
+impl Grouping
+{
+    pub fn fromName(name: &str) -> Result<Self, Error>
+    {
+        match name
+        {
+            "month" => Ok(Grouping::Month),
+            "year" => Ok(Grouping::Year),
+            "account" => Ok(Grouping::Account),
+            _ => Err(rterr!("Unknown grouping: {}", name)),
+        }
+    }
+
+    fn title(&self) -> &'static str
+    {
+        match self
+        {
+            Grouping::Month => "Month",
+            Grouping::Year => "Year",
+            Grouping::Account => "Account",
+        }
+    }
+}
+
+// The sums of a group of orders. The expense is before promotions.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Row
+{
+    pub key: String,
+    pub orders: usize,
+    pub expense: Decimal,
+    pub shipping: Decimal,
+    pub tax: Decimal,
+}
+
+impl Row
+{
+    fn new(key: String) -> Self
+    {
+        Self { key, orders: 0, expense: Decimal::ZERO, shipping: Decimal::ZERO,
+               tax: Decimal::ZERO }
+    }
+
+    pub fn total(&self) -> Decimal
+    {
+        self.expense + self.shipping + self.tax
+    }
+}
+
+// The part of an order that goes into one group.
+struct Share
+{
+    key: String,
+    expense: Decimal,
+    shipping: Decimal,
+    tax: Decimal,
+}
+
+// How much of `amount` each expense posting takes, in proportion to
+// the postings. Everything goes to the first posting if they add up
+// to zero.
+fn split(expenses: &[Posting], amount: Decimal, places: usize) -> Vec<Decimal>
+{
+    let mut postings = expenses.to_vec();
+    if !transaction::distribute(&mut postings, amount, places)
+    {
+        postings[0].amount += amount;
+    }
+    postings.iter().zip(expenses).map(|(p, e)| p.amount - e.amount).collect()
+}
+
+fn shares(order: &Order, config: &Config, grouping: Grouping) -> Vec<Share>
+{
+    let t = order.transaction(config);
+    let expenses: Vec<Posting> = t.postings.into_iter().filter(|p| p.expense)
+        .collect();
+    let key = match grouping
+    {
+        Grouping::Month => t.date.format("%Y-%m").to_string(),
+        Grouping::Year => t.date.format("%Y").to_string(),
+        Grouping::Account => {
+            let places = order.marketplace.decimalPlaces();
+            let shipping = split(&expenses, order.shipping(), places);
+            let tax = split(&expenses, order.tax(), places);
+            return expenses.iter().zip(shipping).zip(tax).map(
+                |((p, shipping), tax)| Share {
+                    key: p.account.clone(), expense: p.amount, shipping, tax,
+                }).collect();
+        },
+    };
+    vec![Share {
+        key,
+        expense: expenses.iter().map(|p| p.amount).sum(),
+        shipping: order.shipping(),
+        tax: order.tax(),
+    }]
+}
+
+// Sum up the orders by `grouping`. The rows are sorted by key. Tax and
+// shipping are always taken apart from the expense here, whatever the
+// allocation in the config.
+pub fn summarize(orders: &[Order], config: &Config, grouping: Grouping) -> Vec<Row>
+{
+    let config = Config {
+        tax_allocation: Allocation::Separate,
+        shipping_allocation: Allocation::Separate,
+        ..config.clone()
+    };
+    let mut rows: BTreeMap<String, (Row, HashSet<&str>)> = BTreeMap::new();
+    for order in orders
+    {
+        for share in shares(order, &config, grouping)
+        {
+            let (row, order_numbers) = rows.entry(share.key).or_insert_with_key(
+                |key| (Row::new(key.clone()), HashSet::new()));
+            row.expense += share.expense;
+            row.shipping += share.shipping;
+            row.tax += share.tax;
+            order_numbers.insert(&order.order_number);
+        }
+    }
+    rows.into_values().map(|(mut row, order_numbers)| {
+        row.orders = order_numbers.len();
+        row
+    }).collect()
+}
+
+// The sum of all the rows. Orders in several rows are counted more
+// than once.
+fn totalRow(rows: &[Row]) -> Row
+{
+    let mut total = Row::new(String::from("Total"));
+    for row in rows
+    {
+        total.orders += row.orders;
+        total.expense += row.expense;
+        total.shipping += row.shipping;
+        total.tax += row.tax;
+    }
+    total
+}
+
+fn fields(row: &Row, places: usize) -> Vec<String>
+{
+    vec![row.key.clone(), row.orders.to_string(),
+         format!("{:.*}", places, row.expense),
+         format!("{:.*}", places, row.shipping),
+         format!("{:.*}", places, row.tax),
+         format!("{:.*}", places, row.total())]
+}
+
+fn header(grouping: Grouping) -> Vec<String>
+{
+    [grouping.title(), "Orders", "Expense", "Shipping", "Tax", "Total"]
+        .iter().map(|s| s.to_string()).collect()
+}
+
+// Format the rows as a plain text table, with a total at the bottom.
+pub fn table(rows: &[Row], grouping: Grouping, places: usize) -> String
+{
+    let mut lines: Vec<Vec<String>> = vec![header(grouping)];
+    lines.extend(rows.iter().map(|row| fields(row, places)));
+    lines.push(fields(&totalRow(rows), places));
+
+    let mut widths = vec![0; lines[0].len()];
+    for line in &lines
+    {
+        for (width, field) in widths.iter_mut().zip(line)
+        {
+            *width = (*width).max(field.chars().count());
+        }
+    }
+    let format = |line: &Vec<String>| -> String {
+        let mut cells = vec![format!("{:<1$}", line[0], widths[0])];
+        cells.extend(line.iter().zip(&widths).skip(1).map(
+            |(field, width)| format!("{:>1$}", field, width)));
+        cells.join("  ")
+    };
+    let rule = "-".repeat(widths.iter().sum::<usize>() + 2 * (widths.len() - 1));
+
+    let mut result = vec![format(&lines[0]), rule.clone()];
+    result.extend(lines[1..lines.len() - 1].iter().map(format));
+    result.push(rule);
+    result.push(format(&lines[lines.len() - 1]));
+    result.join("\n") + "\n"
+}
+
+// Format the rows as CSV, without the total.
+pub fn toCSV(rows: &[Row], grouping: Grouping, places: usize) -> Result<String, Error>
+{
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(header(grouping)).map_err(
+        |e| rterr!("Failed to write CSV: {}", e))?;
+    for row in rows
+    {
+        writer.write_record(fields(row, places)).map_err(
+            |e| rterr!("Failed to write CSV: {}", e))?;
+    }
+    let data = writer.into_inner().map_err(
+        |e| rterr!("Failed to write CSV: {}", e))?;
+    String::from_utf8(data).map_err(|e| rterr!("Failed to write CSV: {}", e))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::grouping;
+    use crate::rules::Rule;
+
+    fn dec(s: &str) -> Decimal
+    {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn testSummarizeByAccount()
+    {
+        let mut config = Config::default();
+        config.rules.push(Rule { category: Some(String::from("Paperback")),
+                                 title: None,
+                                 account: String::from("Expenses:Books") });
+        let orders = grouping::readOrders(
+            include_str!("../testdata/items.csv").as_bytes(), &config).unwrap().0;
+
+        let rows = summarize(&orders, &config, Grouping::Account);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].key, "Expenses:Books");
+        assert_eq!((rows[0].orders, rows[0].expense, rows[0].tax),
+                   (1, dec("20"), dec("1.90")));
+        assert_eq!(rows[1].key, "Expenses:Misc");
+        assert_eq!((rows[1].orders, rows[1].expense, rows[1].tax),
+                   (2, dec("40"), dec("0.95")));
+
+        let rows = summarize(&orders, &config, Grouping::Month);
+        assert_eq!(rows.len(), 1);
+        assert_eq!((rows[0].key.as_str(), rows[0].orders, rows[0].total()),
+                   ("2021-01", 2, dec("62.85")));
+    }
+}
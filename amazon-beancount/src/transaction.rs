@@ -0,0 +1,200 @@
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+
+use crate::error::Error;
+use crate::config::{Allocation, Config};
+use crate::marketplace::Marketplace;
+
+// The value of a piece of metadata.
+#[derive(Clone, PartialEq, Debug)]
+pub enum MetaValue
+{
+    // A string, which is quoted in formats that need it.
+    Text(String),
+    // Written as it is, like a number or a date.
+    Plain(String),
+}
+
+// One leg of a transaction, with optional posting metadata.
+#[derive(Clone)]
+pub struct Posting
+{
+    pub account: String,
+    pub amount: Decimal,
+    pub meta: Vec<(String, MetaValue)>,
+    // Whether this is the expense of the purchased items, as opposed
+    // to tax, shipping, payment, etc.
+    pub expense: bool,
+}
+
+impl Posting
+{
+    pub fn new(account: &str, amount: Decimal) -> Self
+    {
+        Self { account: account.to_owned(), amount, meta: Vec::new(),
+               expense: false }
+    }
+
+    pub fn newExpense(account: &str, amount: Decimal) -> Self
+    {
+        Self { expense: true, ..Self::new(account, amount) }
+    }
+}
+
+// Add `amount` to the expense postings in proportion to their amounts,
+// rounded to `places` decimal places. The rounding error goes to the
+// last expense posting. Return false and change nothing if the expense
+// postings add up to zero.
+pub fn distribute(postings: &mut [Posting], amount: Decimal, places: usize)
+                  -> bool
+{
+    let base: Decimal = postings.iter().filter(|p| p.expense)
+        .map(|p| p.amount).sum();
+    if base.is_zero()
+    {
+        return false;
+    }
+    let mut left = amount;
+    let mut last = None;
+    for (i, posting) in postings.iter_mut().enumerate()
+    {
+        if posting.expense
+        {
+            let share = (amount * posting.amount / base).round_dp(places as u32);
+            posting.amount += share;
+            left -= share;
+            last = Some(i);
+        }
+    }
+    if let Some(i) = last
+    {
+        postings[i].amount += left;
+    }
+    true
+}
+
+// Post the shipping and the tax of an order or a refund. Each of them
+// goes to its own account, unless its allocation in `config` is
+// proportional and there are expenses to distribute it into.
+pub fn allocate(postings: &mut Vec<Posting>, shipping: Decimal, tax: Decimal,
+                config: &Config, places: usize)
+{
+    for (amount, allocation, account) in [
+        (shipping, config.shipping_allocation, &config.account_shipping),
+        (tax, config.tax_allocation, &config.account_tax)]
+    {
+        if amount.is_zero() || (allocation == Allocation::Proportional &&
+                                distribute(postings, amount, places))
+        {
+            continue;
+        }
+        postings.push(Posting::new(account, amount));
+    }
+}
+
+// A transaction to be written into the ledger. Orders and refunds are
+// turned into these.
+#[derive(Clone)]
+pub struct Transaction
+{
+    pub date: NaiveDate,
+    pub narration: String,
+    // Beancount links, without the “^”.
+    pub links: Vec<String>,
+    pub meta: Vec<(String, MetaValue)>,
+    pub postings: Vec<Posting>,
+    pub marketplace: Marketplace,
+    // The order page of this transaction. This is not written into
+    // the ledger.
+    pub url: String,
+}
+
+impl Transaction
+{
+    // Post all the item expenses to `account` instead.
+    pub fn setExpenseAccount(&mut self, account: &str)
+    {
+        for posting in self.postings.iter_mut().filter(|p| p.expense)
+        {
+            posting.account = account.to_owned();
+        }
+    }
+
+    // An amount as it is written into the ledger, rounded to the
+    // decimal places of the currency.
+    pub fn rounded(&self, amount: Decimal) -> Decimal
+    {
+        amount.round_dp(self.marketplace.decimalPlaces() as u32)
+    }
+
+    // Make sure the postings sum up to exactly zero as they are
+    // written, so that the accounting program will accept the
+    // transaction.
+    pub fn checkBalance(&self) -> Result<(), Error>
+    {
+        let sum: Decimal = self.postings.iter().map(|p| self.rounded(p.amount)).sum();
+        if sum.is_zero()
+        {
+            Ok(())
+        }
+        else
+        {
+            Err(rterr!("Postings do not balance (off by {})", sum))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn dec(s: &str) -> Decimal
+    {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn testDistribute()
+    {
+        let mut postings = vec![
+            Posting::newExpense("Expenses:A", dec("10")),
+            Posting::newExpense("Expenses:B", dec("10")),
+            Posting::newExpense("Expenses:C", dec("10")),
+            Posting::new("Liabilities:Card", dec("-30"))];
+        assert!(distribute(&mut postings, dec("1.00"), 2));
+        let amounts: Vec<Decimal> = postings.iter().map(|p| p.amount).collect();
+        assert_eq!(amounts, vec![dec("10.33"), dec("10.33"), dec("10.34"), dec("-30")]);
+
+        let mut postings = vec![Posting::newExpense("Expenses:A", Decimal::ZERO)];
+        assert!(!distribute(&mut postings, dec("1"), 2));
+        assert_eq!(postings[0].amount, Decimal::ZERO);
+    }
+
+    fn transaction(postings: Vec<Posting>) -> Transaction
+    {
+        Transaction {
+            date: NaiveDate::from_ymd_opt(2021, 1, 31).unwrap(),
+            narration: String::new(),
+            links: Vec::new(),
+            meta: Vec::new(),
+            postings,
+            marketplace: Marketplace::US,
+            url: String::new(),
+        }
+    }
+
+    #[test]
+    fn testCheckBalance()
+    {
+        let t = transaction(vec![Posting::newExpense("Expenses:A", dec("10.004")),
+                                 Posting::new("Liabilities:Card", dec("-10"))]);
+        assert!(t.checkBalance().is_ok());
+
+        // This adds up to zero, but not after rounding to cents.
+        let t = transaction(vec![Posting::newExpense("Expenses:A", dec("0.005")),
+                                 Posting::newExpense("Expenses:B", dec("0.005")),
+                                 Posting::new("Liabilities:Card", dec("-0.01"))]);
+        assert!(t.checkBalance().is_err());
+    }
+}
@@ -4,33 +4,130 @@
 // To Public License, Version 2, as published by Sam Hocevar. See
 // http://www.wtfpl.net/ for more details.
 
+use std::convert::TryInto;
 use std::iter::FromIterator;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use rand::RngCore;
 use rand::SeedableRng;
 use num_bigint::BigUint;
-use num_traits::Pow;
 use num_traits::identities::Zero;
 use num_traits::cast::ToPrimitive;
 use num_integer::Integer;
 use reqwest::blocking as requests;
+use hmac::{Hmac, Mac};
+use sha1::{Digest, Sha1};
 
-use crate::error;
 use crate::error::Error;
+use crate::totp;
+
+// Milliseconds since the Unix epoch on the local clock.
+fn localMillis() -> i64
+{
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+// Read the server time (milliseconds since the Unix epoch) at the
+// beginning of a response.
+fn serverMillis(res: &[u8]) -> Result<i64, Error>
+{
+    let bytes: [u8; 8] = res.get(..8).and_then(|b| b.try_into().ok()).ok_or_else(
+        || error!(RuntimeError, "Response is too short"))?;
+    Ok(i64::from_be_bytes(bytes))
+}
+
+// Where to enroll, and the public key to encrypt the request with.
+pub struct Server
+{
+    // The base URL of the service, without the trailing slash.
+    pub endpoint: String,
+    pub modulus: BigUint,
+    pub exponent: BigUint,
+}
+
+impl Server
+{
+    pub const DEFAULT_ENDPOINT: &'static str = "http://mobile-service.blizzard.com";
+    pub const DEFAULT_MODULUS: &'static str = "955e4bd989f3917d2f15544a7e0504eb9d7bb66b6f8a2fe470e453c779200e5e3ad2e43a02d06c4adbd8d328f1a426b83658e88bfd949b2af4eaf30054673a1419a250fa4cc1278d12855b5b25818d162c6e6ee2ab4a350d401d78f6ddb99711e72626b48bd8b5b0b7f3acf9ea3c9e0005fee59e19136cdb7c83f2ab8b0a2a99";
+    pub const DEFAULT_EXPONENT: &'static str = "101";
+
+    // `modulus` and `exponent` are in hex.
+    pub fn new(endpoint: &str, modulus: &str, exponent: &str) -> Result<Self, Error>
+    {
+        Ok(Self {
+            endpoint: endpoint.trim_end_matches('/').to_owned(),
+            modulus: BigUint::parse_bytes(modulus.as_bytes(), 16)
+                .ok_or_else(|| error!(RuntimeError, "Failed to parse modulus"))?,
+            exponent: BigUint::parse_bytes(exponent.as_bytes(), 16)
+                .ok_or_else(|| error!(RuntimeError, "Failed to parse exponent"))?,
+        })
+    }
+
+    fn url(&self, path: &str) -> String
+    {
+        format!("{}{}", self.endpoint, path)
+    }
+}
+
+// The serial number without dashes, like “US141021451234”.
+fn normalizeSerial(serial: &str) -> String
+{
+    serial.chars().filter(|c| *c != '-').collect::<String>().to_uppercase()
+}
+
+// Restore codes are made of digits and capital letters except I, L, O
+// and S, each standing for a 5-bit value.
+const RESTORE_CODE_CHARS: &[u8] = b"0123456789ABCDEFGHJKMNPQRTUVWXYZ";
+const RESTORE_CODE_LEN: usize = 10;
+const RESTORE_CHALLENGE_LEN: usize = 32;
+// The length of the TOTP key.
+const KEY_LEN: usize = 20;
+
+// The restore code of an authenticator. It is derived from the serial
+// number and the key, and is used to prove the ownership of the key
+// when restoring.
+pub fn restoreCode(serial: &str, key: &[u8]) -> String
+{
+    let mut hasher = Sha1::new();
+    hasher.update(normalizeSerial(serial).as_bytes());
+    hasher.update(key);
+    let hash = hasher.finalize();
+    hash[hash.len() - RESTORE_CODE_LEN..].iter()
+        .map(|b| RESTORE_CODE_CHARS[(b & 0x1f) as usize] as char).collect()
+}
+
+// The 5-bit values of the characters of a restore code.
+fn restoreCodeBytes(code: &str) -> Result<Vec<u8>, Error>
+{
+    let code = code.trim().to_uppercase();
+    if code.len() != RESTORE_CODE_LEN
+    {
+        return Err(error!(RuntimeError, "A restore code has {} characters",
+                          RESTORE_CODE_LEN));
+    }
+    code.bytes().map(|c| RESTORE_CODE_CHARS.iter().position(|x| *x == c)
+                     .map(|i| i as u8).ok_or_else(
+                         || error!(RuntimeError, "Invalid character in restore code")))
+        .collect()
+}
 
 pub struct Authenticator
 {
     // prepare a non-deterministic random number generator:
     rng: rand::rngs::StdRng,
+    server: Server,
     rsa_key: Vec<u8>,
 
     key: Vec<u8>,
     serial: String,
+    // Server time minus local time, in milliseconds.
+    time_offset: i64,
 }
 
 impl Authenticator
 {
-    const REGION: &'static str = "US"; // Must be 2 bytes.
+    pub const REGION: &'static str = "US"; // Must be 2 bytes.
     const MODEL: &'static str = "Motorola RAZR v3";
 
     pub fn key(&self) -> &[u8]
@@ -43,21 +140,65 @@ impl Authenticator
         &self.serial
     }
 
-    pub fn new() -> Self
+    pub fn new(server: Server) -> Self
     {
         Self {
             rng: rand::rngs::StdRng::from_entropy(),
+            server,
             rsa_key: Vec::new(),
             key: Vec::new(),
             serial: String::new(),
+            time_offset: 0,
         }
     }
 
+    // An authenticator that is already enrolled. `time_offset` is the
+    // server time minus the local time in milliseconds, if known.
+    pub fn fromKey(server: Server, key: Vec<u8>, serial: &str, time_offset: i64)
+                   -> Self
+    {
+        Self { key, serial: serial.to_owned(), time_offset, ..Self::new(server) }
+    }
+
+    // Server time minus local time, in milliseconds.
+    pub fn timeOffset(&self) -> i64
+    {
+        self.time_offset
+    }
+
+    // The current time on the server, in milliseconds since the Unix
+    // epoch, as far as we know.
+    pub fn serverTime(&self) -> i64
+    {
+        localMillis() + self.time_offset
+    }
+
+    // Ask the server for its time, and remember the offset from the
+    // local clock. Return the offset in milliseconds.
+    pub fn sync(&mut self) -> Result<i64, Error>
+    {
+        let res = requests::get(self.server.url("/enrollment/time.htm"))
+            .map_err(|e| error!(RuntimeError, "Failed to get server time: {}", e))?
+            .error_for_status().map_err(
+                |e| error!(RuntimeError, "Failed to get server time: {}", e))?
+            .bytes().map_err(
+                |_| error!(RuntimeError, "Failed to get bytes from response"))?;
+        self.time_offset = serverMillis(&res)? - localMillis();
+        Ok(self.time_offset)
+    }
+
+    // The current code by server time, and the number of seconds it is
+    // still valid for.
+    pub fn code(&self) -> (String, u64)
+    {
+        totp::code(&self.key, (self.serverTime().max(0) / 1000) as u64)
+    }
+
     fn randBytes(&mut self, size: usize) -> Vec<u8>
     {
         let mut result: Vec<u8> = vec![0;size];
         self.rng.fill_bytes(&mut result);
-        return result;
+        result
     }
 
     fn requestDataClear(&mut self) -> Vec<u8>
@@ -67,10 +208,7 @@ impl Authenticator
         base.extend(self.rsa_key.iter());
         base.extend(Self::REGION.to_owned().bytes());
         base.extend(Self::MODEL.to_owned().bytes().take(16));
-        for _ in 0..(16 - Self::MODEL.len())
-        {
-            base.push(0);
-        }
+        base.resize(base.len() + 16 - Self::MODEL.len().min(16), 0);
 
         base
     }
@@ -78,13 +216,13 @@ impl Authenticator
     fn encrypt(&mut self, data: &[u8]) -> Result<Vec<u8>, Error>
     {
         let data_num = BigUint::from_bytes_be(data);
-        let modulus = BigUint::parse_bytes(b"955e4bd989f3917d2f15544a7e0504eb9d7bb66b6f8a2fe470e453c779200e5e3ad2e43a02d06c4adbd8d328f1a426b83658e88bfd949b2af4eaf30054673a1419a250fa4cc1278d12855b5b25818d162c6e6ee2ab4a350d401d78f6ddb99711e72626b48bd8b5b0b7f3acf9ea3c9e0005fee59e19136cdb7c83f2ab8b0a2a99", 16)
-            .ok_or_else(|| error!(RuntimeError, "Failed to parse modulo"))?;
-        let exp = BigUint::from(0x101 as u32);
-
-        let mut n = data_num.pow(exp) % modulus;
+        if data_num >= self.server.modulus
+        {
+            return Err(error!(RuntimeError, "RSA modulus is too small"));
+        }
+        let mut n = data_num.modpow(&self.server.exponent, &self.server.modulus);
         let mut result: Vec<u8> = Vec::new();
-        let two_five_six = BigUint::from(256 as u32);
+        let two_five_six = BigUint::from(256_u32);
         while !n.is_zero()
         {
             let (new_n, m) = n.div_rem(&two_five_six);
@@ -95,38 +233,113 @@ impl Authenticator
         Ok(result)
     }
 
-    pub fn makeRequest(&mut self) -> Result<Vec<u8>, Error>
+    // Post `body` to `path` on the server, and return the response.
+    fn post(&self, path: &str, body: Vec<u8>) -> Result<Vec<u8>, Error>
     {
         let client = requests::Client::new();
-        let req_data_clear = self.requestDataClear();
-        let req_data = self.encrypt(&req_data_clear)?;
-        let res = client.post("http://mobile-service.blizzard.com/enrollment/enroll.htm")
-            .body(req_data)
+        let res = client.post(self.server.url(path))
+            .body(body)
             .header("Content-Type", "application/octet-stream")
-            .send().map_err(|_| error!(RuntimeError, "Failed to post"))?;
+            .send().map_err(|e| error!(RuntimeError, "Failed to post: {}", e))?
+            .error_for_status().map_err(
+                |e| error!(RuntimeError, "Request failed: {}", e))?;
         Ok(Vec::from_iter(res.bytes().map_err(
             |_| error!(RuntimeError, "Failed to get bytes from response"))?))
     }
 
+    pub fn makeRequest(&mut self) -> Result<Vec<u8>, Error>
+    {
+        let req_data_clear = self.requestDataClear();
+        let req_data = self.encrypt(&req_data_clear)?;
+        self.post("/enrollment/enroll.htm", req_data)
+    }
+
     pub fn decrypt(&self, res: &[u8]) -> Vec<u8>
     {
-        let data = &res[8..];
-        let mut result = Vec::new();
-        for i in 0..37
-        {
-            result.push(data[i] ^ self.rsa_key[i]);
-        }
-        result
+        res[8..].iter().zip(&self.rsa_key).map(|(d, k)| d ^ k).collect()
     }
 
     pub fn request(&mut self) -> Result<(), Error>
     {
         let res = self.makeRequest()?;
+        if res.len() < 8 + 37
+        {
+            return Err(error!(RuntimeError, "Response is too short"));
+        }
+        // The response starts with the server time.
+        self.time_offset = serverMillis(&res)? - localMillis();
         let res = self.decrypt(&res);
-        self.key.resize(20, 0);
-        self.key.copy_from_slice(&res[..20]);
-        self.serial = std::str::from_utf8(&res[20..]).map_err(
+        self.key.resize(KEY_LEN, 0);
+        self.key.copy_from_slice(&res[..KEY_LEN]);
+        self.serial = std::str::from_utf8(&res[KEY_LEN..]).map_err(
             |_| error!(RuntimeError, "Failed to decode UTF-8"))?.to_owned();
         Ok(())
     }
+
+    pub fn restoreCode(&self) -> String
+    {
+        restoreCode(&self.serial, &self.key)
+    }
+
+    // Get the key of an existing authenticator from the server. The
+    // server sends a challenge for the serial number, and we answer it
+    // with an HMAC keyed by the restore code, together with a one-time
+    // pad. The server then sends the key XOR-ed with the pad.
+    pub fn restore(&mut self, serial: &str, restore_code: &str) -> Result<(), Error>
+    {
+        let code = restoreCodeBytes(restore_code)?;
+        let serial_bytes = normalizeSerial(serial).into_bytes();
+        let challenge = self.post("/enrollment/initiatePaperRestore.htm",
+                                  serial_bytes.clone())?;
+        if challenge.len() != RESTORE_CHALLENGE_LEN
+        {
+            return Err(error!(RuntimeError, "Expected a {}-byte challenge, got {} bytes",
+                              RESTORE_CHALLENGE_LEN, challenge.len()));
+        }
+
+        let mut mac = Hmac::<Sha1>::new_from_slice(&code).unwrap();
+        mac.update(&serial_bytes);
+        mac.update(&challenge);
+        let mut data = mac.finalize().into_bytes().to_vec();
+        let pad = self.randBytes(KEY_LEN);
+        data.extend(&pad);
+
+        let mut body = serial_bytes;
+        body.extend(self.encrypt(&data)?);
+        let res = self.post("/enrollment/validatePaperRestore.htm", body).map_err(
+            |e| error!(RuntimeError, "{} (wrong serial or restore code?)", e))?;
+        if res.len() != KEY_LEN
+        {
+            return Err(error!(RuntimeError, "Expected a {}-byte key, got {} bytes",
+                              KEY_LEN, res.len()));
+        }
+        self.key = res.iter().zip(&pad).map(|(d, k)| d ^ k).collect();
+        self.serial = serial.trim().to_uppercase();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn testRestoreCode()
+    {
+        let code = restoreCode("US-1410-2145-1234", b"12345678901234567890");
+        assert_eq!(code.len(), RESTORE_CODE_LEN);
+        assert!(!code.contains(|c| "ILOS".contains(c)));
+        // Dashes and case in the serial do not matter.
+        assert_eq!(restoreCode("us141021451234", b"12345678901234567890"), code);
+
+        let bytes = restoreCodeBytes(&code.to_lowercase()).unwrap();
+        assert!(bytes.iter().all(|b| *b < 32));
+        let back: String = bytes.iter()
+            .map(|b| RESTORE_CODE_CHARS[*b as usize] as char).collect();
+        assert_eq!(back, code);
+
+        assert!(restoreCodeBytes("ABCDEFGHI").is_err());
+        assert!(restoreCodeBytes("ABCDEFGHIJ").is_err());
+    }
 }
@@ -6,6 +6,9 @@
 
 #![allow(non_snake_case)]
 
+use std::io::IsTerminal;
+use std::path::PathBuf;
+
 use data_encoding::BASE32;
 use qrcode::QrCode;
 use image::Luma;
@@ -13,13 +16,13 @@ use image::Luma;
 #[macro_use]
 mod error;
 mod auth;
+mod totp;
+mod vault;
 
 use crate::error::Error;
+use crate::vault::Vault;
 
-fn formatBytes(bytes: &[u8]) -> String
-{
-    bytes.iter().map(|b| format!("{:x}", b)).collect()
-}
+const PASSPHRASE_VAR: &str = "BATTLE_NET_AUTH_PASSPHRASE";
 
 fn key2Url(key: &[u8], user: &str) -> String
 {
@@ -37,12 +40,299 @@ fn key2Qr(key: &[u8], user: &str, filename: &str) -> Result<(), Error>
     Ok(())
 }
 
-fn main()
+fn vaultPath(opts: &clap::ArgMatches) -> PathBuf
+{
+    opts.get_one::<String>("vault").map(PathBuf::from)
+        .unwrap_or_else(vault::defaultPath)
+}
+
+// Read the passphrase of the vault from the environment, or ask for
+// it. A new vault asks for it twice.
+fn passphrase(path: &std::path::Path) -> Result<String, Error>
 {
+    if let Ok(p) = std::env::var(PASSPHRASE_VAR)
+    {
+        return Ok(p);
+    }
+    let p = rpassword::prompt_password("Vault passphrase: ").map_err(
+        |e| error!(RuntimeError, "Failed to read passphrase: {}", e))?;
+    if !path.exists()
+    {
+        let again = rpassword::prompt_password("Again: ").map_err(
+            |e| error!(RuntimeError, "Failed to read passphrase: {}", e))?;
+        if again != p
+        {
+            return Err(error!(RuntimeError, "Passphrases do not match"));
+        }
+    }
+    Ok(p)
+}
+
+fn openVault(opts: &clap::ArgMatches) -> Result<(Vault, PathBuf, String), Error>
+{
+    let path = vaultPath(opts);
+    let passphrase = passphrase(&path)?;
+    let vault = Vault::load(&path, &passphrase)?;
+    Ok((vault, path, passphrase))
+}
+
+fn findEntry<'a>(vault: &'a Vault, name: &str) -> Result<&'a vault::Entry, Error>
+{
+    vault.find(name).ok_or_else(
+        || error!(RuntimeError, "{} is not in the vault", name))
+}
+
+fn list(opts: &clap::ArgMatches) -> Result<(), Error>
+{
+    let (vault, _, _) = openVault(opts)?;
+    for entry in vault.entries()
+    {
+        println!("{}  {}  {}  {}", entry.name, entry.serial, entry.region,
+                 entry.created.format("%F %T UTC"));
+    }
+    Ok(())
+}
+
+// Show an authenticator. With --sync, the new time offset is saved in
+// the vault.
+fn show(opts: &clap::ArgMatches) -> Result<(), Error>
+{
+    let (mut vault, path, passphrase) = openVault(opts)?;
+    let entry = findEntry(&vault, opts.get_one::<String>("NAME").unwrap())?.clone();
+    println!("Name: {}", entry.name);
+    println!("Serial number: {}", entry.serial);
+    println!("Region: {}", entry.region);
+    println!("Created: {}", entry.created.format("%F %T UTC"));
+    println!("Key: {}", entry.key);
+    let key = decodeKey(&entry.key)?;
+    println!("Restore code: {}", auth::restoreCode(&entry.serial, &key));
+    println!("OTP URL: {}", key2Url(&key, &entry.name));
+    let bnauth = authenticator(opts, key, &entry.serial, entry.time_offset)?;
+    let (code, remaining) = bnauth.code();
+    println!("Code: {} ({} seconds left)", code, remaining);
+    if bnauth.timeOffset() != entry.time_offset
+    {
+        saveTimeOffset(&mut vault, &entry.name, bnauth.timeOffset(), &path,
+                       &passphrase)?;
+    }
+    Ok(())
+}
+
+fn remove(opts: &clap::ArgMatches) -> Result<(), Error>
+{
+    let (mut vault, path, passphrase) = openVault(opts)?;
+    let entry = vault.remove(opts.get_one::<String>("NAME").unwrap())?;
+    vault.save(&path, &passphrase)?;
+    println!("Removed {} ({}).", entry.name, entry.serial);
+    Ok(())
+}
+
+fn decodeKey(key: &str) -> Result<Vec<u8>, Error>
+{
+    BASE32.decode(key.trim().to_uppercase().as_bytes()).map_err(
+        |_| error!(RuntimeError, "Invalid base32 key"))
+}
+
+// An enrolled authenticator, synchronized with the server time if
+// asked to. Otherwise the codes are by the local clock plus
+// `time_offset`.
+fn authenticator(opts: &clap::ArgMatches, key: Vec<u8>, serial: &str,
+                 time_offset: i64) -> Result<auth::Authenticator, Error>
+{
+    let mut bnauth = auth::Authenticator::fromKey(server(opts)?, key, serial,
+                                                  time_offset);
+    if opts.get_flag("sync")
+    {
+        bnauth.sync()?;
+    }
+    Ok(bnauth)
+}
+
+// Read a base32-encoded key from stdin, so that it is not on the
+// command line. It is not echoed if stdin is a terminal.
+fn readKey() -> Result<String, Error>
+{
+    if std::io::stdin().is_terminal()
+    {
+        return rpassword::prompt_password("Key: ").map_err(
+            |e| error!(RuntimeError, "Failed to read key: {}", e));
+    }
+    let mut key = String::new();
+    std::io::stdin().read_line(&mut key).map_err(
+        |e| error!(RuntimeError, "Failed to read key: {}", e))?;
+    Ok(key)
+}
+
+// Print the current code of an authenticator in the vault, or of the
+// key read from stdin if no name (or “-”) is given, and how long it is
+// valid for. With --sync, the new time offset of the authenticator is
+// saved in the vault.
+fn printCode(opts: &clap::ArgMatches) -> Result<(), Error>
+{
+    let (code, remaining) = match opts.get_one::<String>("NAME")
+        .filter(|name| *name != "-")
+    {
+        Some(name) => {
+            let (mut vault, path, passphrase) = openVault(opts)?;
+            let entry = findEntry(&vault, name)?.clone();
+            let bnauth = authenticator(opts, decodeKey(&entry.key)?, &entry.serial,
+                                       entry.time_offset)?;
+            if bnauth.timeOffset() != entry.time_offset
+            {
+                saveTimeOffset(&mut vault, &entry.name, bnauth.timeOffset(), &path,
+                               &passphrase)?;
+            }
+            bnauth.code()
+        },
+        None => authenticator(opts, decodeKey(&readKey()?)?, "", 0)?.code(),
+    };
+    println!("{} ({} seconds left)", code, remaining);
+    Ok(())
+}
+
+// Remember the time offset of the authenticator `name` in the vault.
+fn saveTimeOffset(vault: &mut Vault, name: &str, offset: i64, path: &std::path::Path,
+                  passphrase: &str) -> Result<(), Error>
+{
+    vault.findMut(name).ok_or_else(
+        || error!(RuntimeError, "{} is not in the vault", name))?.time_offset = offset;
+    vault.save(path, passphrase)
+}
+
+// Print the server time, and how far the local clock is off. If an
+// authenticator is named, its time offset is saved in the vault.
+fn sync(opts: &clap::ArgMatches) -> Result<(), Error>
+{
+    let opened = match opts.get_one::<String>("NAME")
+    {
+        Some(name) => {
+            let (vault, path, passphrase) = openVault(opts)?;
+            findEntry(&vault, name)?;
+            Some((vault, path, passphrase, name))
+        },
+        None => None,
+    };
+    let mut bnauth = auth::Authenticator::new(server(opts)?);
+    let offset = bnauth.sync()?;
+    let server_time = chrono::DateTime::from_timestamp_millis(bnauth.serverTime())
+        .ok_or_else(|| error!(RuntimeError, "Invalid server time"))?;
+    println!("Server time: {}", server_time.format("%F %T%.3f UTC"));
+    let drift = offset.abs() as f64 / 1000.0;
+    if offset > 0
+    {
+        println!("The local clock is {:.3} seconds behind the server.", drift);
+    }
+    else if offset < 0
+    {
+        println!("The local clock is {:.3} seconds ahead of the server.", drift);
+    }
+    else
+    {
+        println!("The local clock is in sync with the server.");
+    }
+    if offset.abs() >= 1000 * totp::PERIOD as i64
+    {
+        println!("Codes will be wrong. Use --sync with code and show, or sync NAME.");
+    }
+    if let Some((mut vault, path, passphrase, name)) = opened
+    {
+        saveTimeOffset(&mut vault, name, offset, &path, &passphrase)?;
+        println!("Saved the offset for {}.", name);
+    }
+    Ok(())
+}
+
+fn server(opts: &clap::ArgMatches) -> Result<auth::Server, Error>
+{
+    auth::Server::new(opts.get_one::<String>("endpoint").unwrap(),
+                      opts.get_one::<String>("rsa-modulus").unwrap(),
+                      opts.get_one::<String>("rsa-exponent").unwrap())
+}
+
+// Open the vault for adding `name`, which must not be in it yet.
+fn openVaultToAdd(opts: &clap::ArgMatches, name: &str)
+                  -> Result<(Vault, PathBuf, String), Error>
+{
+    let (vault, path, passphrase) = openVault(opts)?;
+    if vault.find(name).is_some()
+    {
+        return Err(error!(RuntimeError, "{} is already in the vault", name));
+    }
+    Ok((vault, path, passphrase))
+}
+
+// Save a new authenticator in the vault.
+fn saveAuthenticator(bnauth: &auth::Authenticator, name: &str, region: &str,
+                     vault: &mut Vault, path: &std::path::Path, passphrase: &str)
+                     -> Result<(), Error>
+{
+    vault.add(vault::Entry {
+        name: name.to_owned(),
+        serial: bnauth.serial().to_owned(),
+        key: BASE32.encode(bnauth.key()),
+        region: region.to_owned(),
+        created: chrono::Utc::now(),
+        time_offset: bnauth.timeOffset(),
+    })?;
+    vault.save(path, passphrase)?;
+    println!("Saved to {}.", path.to_string_lossy());
+    Ok(())
+}
+
+// Request a new authenticator, and save it in the vault.
+fn enroll(opts: &clap::ArgMatches) -> Result<(), Error>
+{
+    let user = opts.get_one::<String>("USER").unwrap();
+    // Open the vault first, so that a wrong passphrase or a duplicate
+    // name does not waste an authenticator.
+    let (mut vault, path, passphrase) = openVaultToAdd(opts, user)?;
+
+    let mut bnauth = auth::Authenticator::new(server(opts)?);
+    bnauth.request()?;
+    println!("Serial number: {}", bnauth.serial());
+    println!("Restore code: {}", bnauth.restoreCode());
+    saveAuthenticator(&bnauth, user, auth::Authenticator::REGION, &mut vault,
+                      &path, &passphrase)?;
+
+    if let Some(q) = opts.get_one::<String>("qrcode")
+    {
+        key2Qr(bnauth.key(), user, q)?;
+    }
+    else
+    {
+        println!("OTP URL: {}", key2Url(bnauth.key(), user))
+    }
+    Ok(())
+}
+
+// Get the key of an existing authenticator with its serial number and
+// restore code, and save it in the vault.
+fn restore(opts: &clap::ArgMatches) -> Result<(), Error>
+{
+    let name = opts.get_one::<String>("NAME").unwrap();
+    let (mut vault, path, passphrase) = openVaultToAdd(opts, name)?;
+
+    let mut bnauth = auth::Authenticator::new(server(opts)?);
+    bnauth.restore(opts.get_one::<String>("SERIAL").unwrap(),
+                   opts.get_one::<String>("RESTORE_CODE").unwrap())?;
+    println!("Restored {}.", bnauth.serial());
+    let region: String = bnauth.serial().chars().take(2).collect();
+    saveAuthenticator(&bnauth, name, &region, &mut vault, &path, &passphrase)?;
+    println!("OTP URL: {}", key2Url(bnauth.key(), name));
+    Ok(())
+}
+
+fn main() -> Result<(), Error>
+{
+    let sync_arg = clap::Arg::new("sync")
+        .long("sync")
+        .action(clap::ArgAction::SetTrue)
+        .help("Use the server time instead of the local clock.");
     let opts = clap::Command::new("Battle.net authenticator request")
         .version("0.1")
         .author("MetroWind")
         .about("Does awesome things")
+        .args_conflicts_with_subcommands(true)
         .arg(clap::Arg::new("USER")
              .required(true)
              .help("User login name of the account"))
@@ -50,19 +340,79 @@ fn main()
              .long("qr-code")
              .value_name("FILE")
              .help("Generate a QR code to FILE."))
+        .arg(clap::Arg::new("vault")
+             .long("vault")
+             .value_name("FILE")
+             .global(true)
+             .help("The vault of authenticators. Default: $XDG_DATA_HOME/battle-net-auth/vault"))
+        .arg(clap::Arg::new("endpoint")
+             .long("endpoint")
+             .value_name("URL")
+             .global(true)
+             .default_value(auth::Server::DEFAULT_ENDPOINT)
+             .help("The base URL of the enrollment service."))
+        .arg(clap::Arg::new("rsa-modulus")
+             .long("rsa-modulus")
+             .value_name("HEX")
+             .global(true)
+             .default_value(auth::Server::DEFAULT_MODULUS)
+             .hide_default_value(true)
+             .help("The RSA modulus of the enrollment service, in hex. Default: Blizzard's"))
+        .arg(clap::Arg::new("rsa-exponent")
+             .long("rsa-exponent")
+             .value_name("HEX")
+             .global(true)
+             .default_value(auth::Server::DEFAULT_EXPONENT)
+             .help("The RSA exponent of the enrollment service, in hex."))
+        .subcommand(
+            clap::Command::new("code")
+                .about("Print the current 8-digit code.")
+                .arg(clap::Arg::new("NAME")
+                     .help("The name of the authenticator in the vault. Without it, or if it is -, read the key (in base32 as in the OTP URL) from stdin."))
+                .arg(sync_arg.clone()))
+        .subcommand(
+            clap::Command::new("list")
+                .about("List the authenticators in the vault."))
+        .subcommand(
+            clap::Command::new("show")
+                .about("Show an authenticator in the vault, with its key and current code.")
+                .arg(clap::Arg::new("NAME")
+                     .required(true)
+                     .help("The name of the authenticator"))
+                .arg(sync_arg))
+        .subcommand(
+            clap::Command::new("restore")
+                .about("Restore an existing authenticator into the vault.")
+                .arg(clap::Arg::new("NAME")
+                     .required(true)
+                     .help("The name to save the authenticator as"))
+                .arg(clap::Arg::new("SERIAL")
+                     .required(true)
+                     .help("The serial number, like US-1234-5678-9012"))
+                .arg(clap::Arg::new("RESTORE_CODE")
+                     .required(true)
+                     .help("The 10-character restore code")))
+        .subcommand(
+            clap::Command::new("sync")
+                .about("Show the difference between the local clock and the server.")
+                .arg(clap::Arg::new("NAME")
+                     .help("Save the difference for this authenticator in the vault")))
+        .subcommand(
+            clap::Command::new("remove")
+                .about("Remove an authenticator from the vault.")
+                .arg(clap::Arg::new("NAME")
+                     .required(true)
+                     .help("The name of the authenticator")))
         .get_matches();
 
-    let mut bnauth = auth::Authenticator::new();
-    bnauth.request().unwrap();
-    println!("Serial number: {}", bnauth.serial());
-
-    let user = opts.get_one::<String>("USER").unwrap();
-    if let Some(q) = opts.get_one::<String>("qrcode")
+    match opts.subcommand()
     {
-        key2Qr(bnauth.key(), user, q).unwrap();
-    }
-    else
-    {
-        println!("OTP URL: {}", key2Url(bnauth.key(), user))
+        Some(("code", sub_opts)) => printCode(sub_opts),
+        Some(("sync", sub_opts)) => sync(sub_opts),
+        Some(("restore", sub_opts)) => restore(sub_opts),
+        Some(("list", sub_opts)) => list(sub_opts),
+        Some(("show", sub_opts)) => show(sub_opts),
+        Some(("remove", sub_opts)) => remove(sub_opts),
+        _ => enroll(&opts),
     }
 }
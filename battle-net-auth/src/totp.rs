@@ -0,0 +1,71 @@
+// This program is free software. It comes without any warranty, to
+// the extent permitted by applicable law. You can redistribute it
+// and/or modify it under the terms of the Do What The Fuck You Want
+// To Public License, Version 2, as published by Sam Hocevar. See
+// http://www.wtfpl.net/ for more details.
+
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+// Blizzard uses 8-digit codes that change every 30 seconds.
+pub const DIGITS: u32 = 8;
+pub const PERIOD: u64 = 30;
+
+// HOTP from RFC 4226, with HMAC-SHA1.
+pub fn hotp(key: &[u8], counter: u64, digits: u32) -> u32
+{
+    // HMAC takes keys of any length.
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).unwrap();
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // Dynamic truncation.
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let value = u32::from_be_bytes([hash[offset] & 0x7f, hash[offset + 1],
+                                    hash[offset + 2], hash[offset + 3]]);
+    value % 10u32.pow(digits)
+}
+
+// The code at `time` (seconds since the Unix epoch) with Blizzard's
+// parameters, zero-padded, and the number of seconds it is still
+// valid for.
+pub fn code(key: &[u8], time: u64) -> (String, u64)
+{
+    let value = hotp(key, time / PERIOD, DIGITS);
+    (format!("{:01$}", value, DIGITS as usize), PERIOD - time % PERIOD)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const KEY: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn testHotp()
+    {
+        // Appendix D of RFC 4226.
+        let expected = [755224, 287082, 359152, 969429, 338314,
+                        254676, 287922, 162583, 399871, 520489];
+        for (counter, value) in expected.iter().enumerate()
+        {
+            assert_eq!(hotp(KEY, counter as u64, 6), *value);
+        }
+    }
+
+    #[test]
+    fn testTotp()
+    {
+        // The SHA1 vectors in Appendix B of RFC 6238.
+        let vectors = [(59, "94287082"), (1111111109, "07081804"),
+                       (1111111111, "14050471"), (1234567890, "89005924"),
+                       (2000000000, "69279037"), (20000000000, "65353130")];
+        for (time, expected) in vectors.iter()
+        {
+            assert_eq!(code(KEY, *time).0, *expected);
+        }
+        assert_eq!(code(KEY, 59).1, 1);
+        assert_eq!(code(KEY, 60).1, 30);
+    }
+}
@@ -0,0 +1,365 @@
+// This program is free software. It comes without any warranty, to
+// the extent permitted by applicable law. You can redistribute it
+// and/or modify it under the terms of the Do What The Fuck You Want
+// To Public License, Version 2, as published by Sam Hocevar. See
+// http://www.wtfpl.net/ for more details.
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+// An enrolled authenticator.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Entry
+{
+    // The name given by the user at enrollment, usually the account
+    // login.
+    pub name: String,
+    pub serial: String,
+    // The TOTP key, in base32.
+    pub key: String,
+    pub region: String,
+    pub created: DateTime<Utc>,
+    // Server time minus local time, in milliseconds, as of the last
+    // time it was known: at enrollment, or with --sync.
+    #[serde(default)]
+    pub time_offset: i64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Contents
+{
+    #[serde(default)]
+    entries: Vec<Entry>,
+}
+
+// A file of authenticators, encrypted with a passphrase. The file is
+//
+//   MAGIC | Argon2id m_cost, t_cost, p_cost (u32 LE each) | salt | nonce
+//   | XChaCha20-Poly1305 ciphertext of the entries in TOML
+//
+// where everything before the ciphertext is authenticated as
+// associated data.
+pub struct Vault
+{
+    entries: Vec<Entry>,
+}
+
+const MAGIC: &[u8] = b"BNAVAULT1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = MAGIC.len() + 12 + SALT_LEN + NONCE_LEN;
+// How many times the default Argon2 costs a vault file may ask for.
+const MAX_COST_FACTOR: u32 = 4;
+
+// Derive the encryption key from the passphrase.
+fn deriveKey(passphrase: &str, salt: &[u8], params: Params)
+             -> Result<[u8; 32], Error>
+{
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| error!(RuntimeError, "Failed to derive key: {}", e))?;
+    Ok(key)
+}
+
+fn readU32(data: &[u8], offset: usize) -> u32
+{
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2],
+                        data[offset + 3]])
+}
+
+fn encrypt(plain: &[u8], passphrase: &str) -> Result<Vec<u8>, Error>
+{
+    let params = Params::default();
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::rngs::OsRng.fill_bytes(&mut salt);
+    rand::rngs::OsRng.fill_bytes(&mut nonce);
+
+    let mut header = MAGIC.to_vec();
+    for value in [params.m_cost(), params.t_cost(), params.p_cost()]
+    {
+        header.extend(value.to_le_bytes());
+    }
+    header.extend(salt);
+    header.extend(nonce);
+
+    let key = deriveKey(passphrase, &salt, params)?;
+    let cipher = XChaCha20Poly1305::new(&key.into());
+    let sealed = cipher.encrypt(XNonce::from_slice(&nonce),
+                                Payload { msg: plain, aad: &header })
+        .map_err(|_| error!(RuntimeError, "Failed to encrypt vault"))?;
+    header.extend(sealed);
+    Ok(header)
+}
+
+// The Argon2 parameters in the header of a vault file. They are
+// checked against a ceiling before use, so that a corrupted or crafted
+// file cannot make us allocate gigabytes or run for minutes before the
+// authentication fails.
+fn readParams(header: &[u8]) -> Result<Params, Error>
+{
+    let m_cost = readU32(header, MAGIC.len());
+    let t_cost = readU32(header, MAGIC.len() + 4);
+    let p_cost = readU32(header, MAGIC.len() + 8);
+    let max = Params::default();
+    if m_cost > MAX_COST_FACTOR * max.m_cost()
+        || t_cost > MAX_COST_FACTOR * max.t_cost()
+        || p_cost > MAX_COST_FACTOR * max.p_cost()
+    {
+        return Err(error!(RuntimeError,
+                          "Vault parameters are too expensive: m_cost={}, \
+                           t_cost={}, p_cost={}", m_cost, t_cost, p_cost));
+    }
+    Params::new(m_cost, t_cost, p_cost, None)
+        .map_err(|e| error!(RuntimeError, "Invalid vault parameters: {}", e))
+}
+
+fn decrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>, Error>
+{
+    if data.len() < HEADER_LEN || &data[..MAGIC.len()] != MAGIC
+    {
+        return Err(error!(RuntimeError, "Not a vault file"));
+    }
+    let (header, sealed) = data.split_at(HEADER_LEN);
+    let params = readParams(header)?;
+    let salt_start = MAGIC.len() + 12;
+    let salt = &header[salt_start..salt_start + SALT_LEN];
+    let nonce = &header[salt_start + SALT_LEN..];
+
+    let key = deriveKey(passphrase, salt, params)?;
+    let cipher = XChaCha20Poly1305::new(&key.into());
+    cipher.decrypt(XNonce::from_slice(nonce), Payload { msg: sealed, aad: header })
+        .map_err(|_| error!(RuntimeError,
+                            "Failed to decrypt vault. Wrong passphrase?"))
+}
+
+// The default location of the vault.
+pub fn defaultPath() -> PathBuf
+{
+    let data_dir = match env::var_os("XDG_DATA_HOME")
+    {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME").unwrap_or_default())
+            .join(".local").join("share"),
+    };
+    data_dir.join("battle-net-auth").join("vault")
+}
+
+impl Vault
+{
+    pub fn new() -> Self
+    {
+        Self { entries: Vec::new() }
+    }
+
+    pub fn fromBytes(data: &[u8], passphrase: &str) -> Result<Self, Error>
+    {
+        let plain = decrypt(data, passphrase)?;
+        let text = std::str::from_utf8(&plain).map_err(
+            |_| error!(RuntimeError, "Failed to decode vault"))?;
+        let contents: Contents = toml::from_str(text).map_err(
+            |e| error!(RuntimeError, "Failed to parse vault: {}", e))?;
+        Ok(Self { entries: contents.entries })
+    }
+
+    pub fn toBytes(&self, passphrase: &str) -> Result<Vec<u8>, Error>
+    {
+        let contents = Contents { entries: self.entries.clone() };
+        let text = toml::to_string(&contents).map_err(
+            |e| error!(RuntimeError, "Failed to serialize vault: {}", e))?;
+        encrypt(text.as_bytes(), passphrase)
+    }
+
+    // Load the vault at `path`. A vault that does not exist yet is
+    // empty.
+    pub fn load(path: &Path, passphrase: &str) -> Result<Self, Error>
+    {
+        if !path.exists()
+        {
+            return Ok(Self::new());
+        }
+        let data = fs::read(path).map_err(
+            |e| error!(RuntimeError, "Failed to read {}: {}",
+                       path.to_string_lossy(), e))?;
+        Self::fromBytes(&data, passphrase)
+    }
+
+    // Write the vault to `path`, only readable by the user. The old
+    // file is replaced only after the new one is completely written
+    // to `path` plus “.tmp”, which is removed if that fails.
+    pub fn save(&self, path: &Path, passphrase: &str) -> Result<(), Error>
+    {
+        let data = self.toBytes(passphrase)?;
+        if let Some(dir) = path.parent()
+        {
+            fs::create_dir_all(dir).map_err(
+                |e| error!(RuntimeError, "Failed to create {}: {}",
+                           dir.to_string_lossy(), e))?;
+        }
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let write = || -> std::io::Result<()> {
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o600);
+            }
+            let mut file = options.open(&tmp)?;
+            file.write_all(&data)?;
+            file.sync_all()?;
+            fs::rename(&tmp, path)
+        };
+        write().map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            error!(RuntimeError, "Failed to write {}: {}", path.to_string_lossy(), e)
+        })
+    }
+
+    pub fn entries(&self) -> &[Entry]
+    {
+        &self.entries
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Entry>
+    {
+        self.entries.iter().find(|e| e.name == name)
+    }
+
+    pub fn findMut(&mut self, name: &str) -> Option<&mut Entry>
+    {
+        self.entries.iter_mut().find(|e| e.name == name)
+    }
+
+    // Add an entry. Names must be unique.
+    pub fn add(&mut self, entry: Entry) -> Result<(), Error>
+    {
+        if self.find(&entry.name).is_some()
+        {
+            return Err(error!(RuntimeError, "{} is already in the vault",
+                              entry.name));
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<Entry, Error>
+    {
+        let i = self.entries.iter().position(|e| e.name == name).ok_or_else(
+            || error!(RuntimeError, "{} is not in the vault", name))?;
+        Ok(self.entries.remove(i))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn entry(name: &str) -> Entry
+    {
+        Entry {
+            name: name.to_owned(),
+            serial: String::from("US-1234-5678-9012"),
+            key: String::from("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"),
+            region: String::from("US"),
+            created: DateTime::from_timestamp(1600000000, 0).unwrap(),
+            time_offset: -1500,
+        }
+    }
+
+    #[test]
+    fn testRoundTrip()
+    {
+        let mut vault = Vault::new();
+        vault.add(entry("alice")).unwrap();
+        vault.add(entry("bob")).unwrap();
+        assert!(vault.add(entry("bob")).is_err());
+
+        let data = vault.toBytes("hunter2").unwrap();
+        assert!(Vault::fromBytes(&data, "hunter3").is_err());
+        let mut vault = Vault::fromBytes(&data, "hunter2").unwrap();
+        assert_eq!(vault.entries(), &[entry("alice"), entry("bob")]);
+
+        vault.findMut("bob").unwrap().time_offset = 42;
+        assert_eq!(vault.find("bob").unwrap().time_offset, 42);
+        assert_eq!(vault.remove("alice").unwrap(), entry("alice"));
+        assert!(vault.remove("alice").is_err());
+        assert!(vault.find("bob").is_some());
+    }
+
+    #[test]
+    fn testTampered()
+    {
+        let mut vault = Vault::new();
+        vault.add(entry("alice")).unwrap();
+        let mut data = vault.toBytes("hunter2").unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(Vault::fromBytes(&data, "hunter2").is_err());
+        assert!(Vault::fromBytes(b"garbage", "hunter2").is_err());
+    }
+
+    #[test]
+    fn testSave()
+    {
+        let dir = env::temp_dir().join(
+            format!("battle-net-auth-vault-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("vault.tmp"), "not mine").unwrap();
+        let mut vault = Vault::new();
+        vault.add(entry("alice")).unwrap();
+
+        // The file next to it with the same stem is left alone.
+        vault.save(&dir.join("vault.db"), "hunter2").unwrap();
+        assert_eq!(Vault::load(&dir.join("vault.db"), "hunter2").unwrap().entries(),
+                   &[entry("alice")]);
+        assert_eq!(fs::read_to_string(dir.join("vault.tmp")).unwrap(), "not mine");
+        assert!(!dir.join("vault.db.tmp").exists());
+
+        // A directory cannot be replaced, and the new file is removed.
+        assert!(vault.save(&dir.join("sub"), "hunter2").is_err());
+        let mut names: Vec<_> = fs::read_dir(&dir).unwrap()
+            .map(|e| e.unwrap().file_name()).collect();
+        names.sort();
+        assert_eq!(names, ["sub", "vault.db", "vault.tmp"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn testExpensiveParams()
+    {
+        let mut vault = Vault::new();
+        vault.add(entry("alice")).unwrap();
+        let data = vault.toBytes("hunter2").unwrap();
+        let default = Params::default();
+        for (i, max) in [default.m_cost(), default.t_cost(), default.p_cost()]
+            .iter().enumerate()
+        {
+            let offset = MAGIC.len() + 4 * i;
+            let mut data = data.clone();
+            data[offset..offset + 4].copy_from_slice(
+                &(MAX_COST_FACTOR * max + 1).to_le_bytes());
+            match Vault::fromBytes(&data, "hunter2")
+            {
+                Err(e) => assert!(e.to_string().contains("too expensive"), "{}", e),
+                Ok(_) => panic!("Expensive parameters are accepted"),
+            }
+        }
+    }
+}
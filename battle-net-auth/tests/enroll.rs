@@ -0,0 +1,301 @@
+// This program is free software. It comes without any warranty, to
+// the extent permitted by applicable law. You can redistribute it
+// and/or modify it under the terms of the Do What The Fuck You Want
+// To Public License, Version 2, as published by Sam Hocevar. See
+// http://www.wtfpl.net/ for more details.
+
+#![allow(non_snake_case)]
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use std::thread;
+
+use hmac::{Hmac, Mac};
+use num_bigint::BigUint;
+use sha1::{Digest, Sha1};
+
+// A test RSA key pair, with exponent 0x10001.
+const MODULUS: &str = "9ff794ac56ff31bf18f56e06faa3f94fc373d9d534c5a0a67b4d4ffbec5c122f5bd0a0e203025ca2d6e11396b57051b832ba8d20a9c23ee21a430ff1ec0dceaf64288fbd";
+const PRIVATE_EXPONENT: &str = "6ac5569f9d45a20f99fa1ee15ba3dcc14784e90d6cb6c81eb6c808adeeba198d30ab426ab2fbbbb72ed1861f8ae79c42d800366c7971362b42ec36227bfe8e3098fdd8c5";
+
+const KEY: &[u8] = b"12345678901234567890";
+const SERIAL: &str = "US-1410-2145-1234";
+const SERIAL_NORMALIZED: &[u8] = b"US141021451234";
+const CHALLENGE: [u8; 32] = [7; 32];
+const PASSPHRASE: &str = "hunter2";
+
+// The time on the mock server, which is 1 second into a 30-second
+// period. RFC 6238 has the code of KEY at this time.
+const SERVER_TIME: u64 = 1_111_111_111_000;
+const SERVER_CODE: &str = "14050471";
+
+fn hex(s: &str) -> BigUint
+{
+    BigUint::parse_bytes(s.as_bytes(), 16).unwrap()
+}
+
+// Answer an enrollment request like Blizzard's server: decrypt the
+// request to find the one-time pad, and reply with the server time
+// followed by the key and serial XOR-ed with the pad.
+fn enroll(body: &[u8]) -> Vec<u8>
+{
+    let clear = BigUint::from_bytes_be(body)
+        .modpow(&hex(PRIVATE_EXPONENT), &hex(MODULUS)).to_bytes_be();
+    // Version, pad, region and model.
+    assert_eq!(clear.len(), 1 + 37 + 2 + 16);
+    assert_eq!(clear[0], 1);
+    assert_eq!(&clear[38..40], b"US");
+    let pad = &clear[1..38];
+
+    let mut response = SERVER_TIME.to_be_bytes().to_vec();
+    response.extend(KEY.iter().chain(SERIAL.as_bytes()).zip(pad)
+                    .map(|(b, p)| b ^ p));
+    response
+}
+
+// The restore code of KEY, as 5-bit values: the last 10 bytes of the
+// SHA1 of the serial and the key.
+fn restoreCodeValues() -> Vec<u8>
+{
+    let hash = Sha1::new().chain_update(SERIAL_NORMALIZED).chain_update(KEY)
+        .finalize();
+    hash[10..].iter().map(|b| b & 0x1f).collect()
+}
+
+// Digits, then letters skipping I, L, O and S.
+fn restoreCode() -> String
+{
+    restoreCodeValues().iter().map(|v| {
+        if *v < 10
+        {
+            return (b'0' + v) as char;
+        }
+        let mut c = b'A' + v - 10;
+        for skipped in b"ILOS"
+        {
+            if c >= *skipped
+            {
+                c += 1;
+            }
+        }
+        c as char
+    }).collect()
+}
+
+// Check the answer to the restore challenge, and reply with the key
+// XOR-ed with the pad in the answer. Return None if the answer is
+// wrong.
+fn validateRestore(body: &[u8]) -> Option<Vec<u8>>
+{
+    assert_eq!(&body[..SERIAL_NORMALIZED.len()], SERIAL_NORMALIZED);
+    let clear = BigUint::from_bytes_be(&body[SERIAL_NORMALIZED.len()..])
+        .modpow(&hex(PRIVATE_EXPONENT), &hex(MODULUS)).to_bytes_be();
+    // Leading zeros are lost in the RSA round trip.
+    let mut data = vec![0; 40 - clear.len()];
+    data.extend(clear);
+    let (answer, pad) = data.split_at(20);
+
+    let mut mac = Hmac::<Sha1>::new_from_slice(&restoreCodeValues()).unwrap();
+    mac.update(SERIAL_NORMALIZED);
+    mac.update(&CHALLENGE);
+    mac.verify_slice(answer).ok()?;
+    Some(KEY.iter().zip(pad).map(|(k, p)| k ^ p).collect())
+}
+
+// Start a stand-in of Blizzard's server, and return its address.
+fn startMockServer() -> String
+{
+    startServer(None)
+}
+
+// Like startMockServer(), but the responses to `truncated` lose their
+// last byte.
+fn startServer(truncated: Option<&'static str>) -> String
+{
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let address = format!("http://{}", server.server_addr().to_ip().unwrap());
+    thread::spawn(move || {
+        for mut request in server.incoming_requests()
+        {
+            let mut body = Vec::new();
+            request.as_reader().read_to_end(&mut body).unwrap();
+            let mut response = match (request.method(), request.url())
+            {
+                (tiny_http::Method::Post, "/enrollment/enroll.htm") => enroll(&body),
+                (tiny_http::Method::Get, "/enrollment/time.htm") =>
+                    SERVER_TIME.to_be_bytes().to_vec(),
+                (tiny_http::Method::Post, "/enrollment/initiatePaperRestore.htm") => {
+                    assert_eq!(body, SERIAL_NORMALIZED);
+                    CHALLENGE.to_vec()
+                },
+                (tiny_http::Method::Post, "/enrollment/validatePaperRestore.htm") =>
+                    match validateRestore(&body)
+                    {
+                        Some(response) => response,
+                        None => {
+                            request.respond(tiny_http::Response::empty(403)).unwrap();
+                            continue;
+                        },
+                    },
+                _ => {
+                    request.respond(tiny_http::Response::empty(404)).unwrap();
+                    continue;
+                },
+            };
+            if truncated == Some(request.url())
+            {
+                response.pop();
+            }
+            request.respond(tiny_http::Response::from_data(response)).unwrap();
+        }
+    });
+    address
+}
+
+fn run(vault: &PathBuf, args: &[&str]) -> Output
+{
+    Command::new(env!("CARGO_BIN_EXE_battle-net-auth"))
+        .args(args)
+        .arg("--vault").arg(vault)
+        .env("BATTLE_NET_AUTH_PASSPHRASE", PASSPHRASE)
+        .output().unwrap()
+}
+
+// Like run(), but write `input` to the stdin of the program.
+fn runWithInput(vault: &PathBuf, args: &[&str], input: &str) -> Output
+{
+    let mut child = Command::new(env!("CARGO_BIN_EXE_battle-net-auth"))
+        .args(args)
+        .arg("--vault").arg(vault)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn().unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn testEnrollWithMockServer()
+{
+    let dir = std::env::temp_dir().join(
+        format!("battle-net-auth-test-{}", std::process::id()));
+    let vault = dir.join("vault");
+    let endpoint = startMockServer();
+
+    let output = run(&vault, &["--endpoint", &endpoint, "--rsa-modulus", MODULUS,
+                               "--rsa-exponent", "10001", "alice"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}",
+            String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains(&format!("Serial number: {}", SERIAL)), "{}", stdout);
+    assert!(stdout.contains("secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"), "{}", stdout);
+    assert!(stdout.contains(&format!("Restore code: {}", restoreCode())), "{}", stdout);
+
+    let output = run(&vault, &["show", "alice"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}",
+            String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("Key: GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"), "{}", stdout);
+    assert!(stdout.contains(&format!("Serial number: {}", SERIAL)), "{}", stdout);
+    // The offset from the server time at enrollment is kept, so the
+    // code is by the server time without --sync.
+    assert!(stdout.contains(&format!("Code: {}", SERVER_CODE)), "{}", stdout);
+
+    let output = run(&vault, &["code", "alice"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}",
+            String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.starts_with(SERVER_CODE), "{}", stdout);
+
+    let output = run(&vault, &["sync", "alice", "--endpoint", &endpoint]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}",
+            String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("Saved the offset for alice"), "{}", stdout);
+    let output = run(&vault, &["show", "alice"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(&format!("Code: {}", SERVER_CODE)), "{}", stdout);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn testSync()
+{
+    let vault = std::env::temp_dir().join("battle-net-auth-test-unused");
+    let endpoint = startMockServer();
+
+    let output = run(&vault, &["sync", "--endpoint", &endpoint]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}",
+            String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("Server time: 2005-03-18 01:58:31"), "{}", stdout);
+    assert!(stdout.contains("ahead of the server"), "{}", stdout);
+
+    // The key is read from stdin, with or without “-”.
+    for args in [&["code", "--sync", "--endpoint", &endpoint][..],
+                 &["code", "-", "--sync", "--endpoint", &endpoint][..]]
+    {
+        let output = runWithInput(&vault, args, "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\n");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{}",
+                String::from_utf8_lossy(&output.stderr));
+        assert!(stdout.starts_with(SERVER_CODE), "{}", stdout);
+    }
+}
+
+#[test]
+fn testRestore()
+{
+    let dir = std::env::temp_dir().join(
+        format!("battle-net-auth-restore-test-{}", std::process::id()));
+    let vault = dir.join("vault");
+    let endpoint = startMockServer();
+    let server = ["--endpoint", &endpoint, "--rsa-modulus", MODULUS,
+                  "--rsa-exponent", "10001"];
+
+    let output = run(&vault, &[&["restore", "bob", SERIAL, "ABCDEFGHJK"][..],
+                               &server[..]].concat());
+    assert!(!output.status.success());
+
+    let code = restoreCode().to_lowercase();
+    let output = run(&vault, &[&["restore", "bob", SERIAL, &code][..],
+                               &server[..]].concat());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}",
+            String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"), "{}", stdout);
+
+    let output = run(&vault, &["show", "bob"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    assert!(stdout.contains(&format!("Serial number: {}", SERIAL)), "{}", stdout);
+    assert!(stdout.contains(&format!("Restore code: {}", restoreCode())), "{}", stdout);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn testRestoreTruncated()
+{
+    let dir = std::env::temp_dir().join(
+        format!("battle-net-auth-truncated-test-{}", std::process::id()));
+    let vault = dir.join("vault");
+    let code = restoreCode();
+    for (path, message) in [("/enrollment/initiatePaperRestore.htm", "challenge"),
+                            ("/enrollment/validatePaperRestore.htm", "key")]
+    {
+        let endpoint = startServer(Some(path));
+        let output = run(&vault, &["restore", "bob", SERIAL, &code, "--endpoint",
+                                   &endpoint, "--rsa-modulus", MODULUS,
+                                   "--rsa-exponent", "10001"]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success());
+        assert!(stderr.contains(message), "{}", stderr);
+    }
+    // Nothing is saved.
+    assert!(!vault.exists());
+    let _ = std::fs::remove_dir_all(&dir);
+}